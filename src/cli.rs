@@ -2,6 +2,9 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
+use crate::logger::{LogFormat, SinkSpec};
+use crate::rules::AuditLevel;
+
 #[derive(Parser, Debug)]
 #[command(name = "fuse-access-guard", about = "FUSE-based file access restriction wrapper")]
 #[command(trailing_var_arg = true)]
@@ -10,14 +13,135 @@ pub struct CliArgs {
     #[arg(short, long)]
     pub quiet: bool,
 
-    /// Write access-denied logs to this file
+    /// Print the computed mount plan to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Write access-denied logs to this file (appended to)
     #[arg(long, value_name = "PATH")]
     pub log_file: Option<PathBuf>,
 
+    /// Log destination: stderr, file:PATH, syslog or journald. Repeatable;
+    /// overrides `accessGuard.log.sinks` (default: stderr)
+    #[arg(long, value_name = "SINK")]
+    pub log_sink: Vec<SinkSpec>,
+
+    /// Empty existing log files instead of appending to them
+    #[arg(long)]
+    pub log_truncate: bool,
+
+    /// Rotate log files to `<PATH>.1` once they would exceed this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub log_max_size: Option<u64>,
+
+    /// Number of rotated log files to keep, `<PATH>.1` being the newest
+    /// (default: 1)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub log_max_files: Option<u32>,
+
+    /// Format of access-denied logs
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Coalesce identical (pid, path, operation) events within this many
+    /// milliseconds into one line plus a repeat count (0 disables)
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub log_dedup_window: u64,
+
+    /// Maximum log lines per second; excess events are counted in a
+    /// "suppressed" notice (0 means unlimited)
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub log_rate_limit: u32,
+
+    /// Which accesses to log: only denials, also allowed opens of paths
+    /// listed under `accessGuard.sensitive`, or also all allowed opens below
+    /// the current directory
+    #[arg(long, value_name = "LEVEL", value_enum, default_value_t = AuditLevel::Denied)]
+    pub audit: AuditLevel,
+
+    /// Print an access summary to stderr when the command exits
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Write an access summary to this file when the command exits
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Format of the --report file
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = LogFormat::Text, requires = "report")]
+    pub report_format: LogFormat,
+
     /// Executable paths to exclude from access restrictions
     #[arg(long, value_name = "PATH")]
     pub exclude_exec: Vec<String>,
 
+    /// Hide files denied for reading from directory listings and lookups
+    #[arg(long)]
+    pub hide: bool,
+
+    /// Also deny by inode identity, protecting denied files under any name
+    /// (hardlinks) reachable inside the mounts
+    #[arg(long)]
+    pub protect_inodes: bool,
+
+    /// Directory to scan for hardlinks of denied files with --protect-inodes
+    /// (default: current directory)
+    #[arg(long, value_name = "PATH", requires = "protect_inodes")]
+    pub inode_scan_root: Vec<PathBuf>,
+
+    /// Keep the rules loaded at startup even if `.claude/settings.json`
+    /// changes while the command runs
+    #[arg(long)]
+    pub no_reload: bool,
+
+    /// Allow the command to change, delete or rename `.claude/settings.json`
+    /// (by default it is protected whenever it contributes rules). Rules
+    /// removed from it are then kept until restart.
+    #[arg(long)]
+    pub no_protect_settings: bool,
+
+    /// Record every access below the current directory and print a
+    /// suggested policy (settings.json fragment) when the command exits
+    #[arg(long)]
+    pub learn: bool,
+
+    /// Write the suggested policy of --learn to PATH instead of stderr
+    /// (implies --learn)
+    #[arg(long, value_name = "PATH")]
+    pub learn_output: Option<PathBuf>,
+
+    /// Deny reading files whose content looks like credentials (private
+    /// keys, AWS access key IDs, GitHub tokens, random `*_KEY=` values)
+    #[arg(long)]
+    pub scan_secrets: bool,
+
+    /// Largest file scanned by --scan-secrets
+    #[arg(long, value_name = "BYTES", default_value_t = crate::scanner::DEFAULT_MAX_SIZE)]
+    pub scan_max_size: u64,
+
+    /// Largest file served with secrets masked by Redact(...) rules;
+    /// larger files are denied
+    #[arg(long, value_name = "BYTES", default_value_t = crate::redact::DEFAULT_MAX_SIZE)]
+    pub redact_max_size: u64,
+
+    /// Log accesses that rules would deny as WOULD_DENY without blocking them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Deny accesses matching rules; `--enforce=false` is the same as --dry-run
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    pub enforce: bool,
+
+    /// Serve a control socket for inspecting and changing rules while the
+    /// command runs; its path is exported as FUSE_ACCESS_GUARD_SOCKET
+    #[arg(long)]
+    pub control: bool,
+
+    /// Path of the control socket (implies --control; default:
+    /// `$XDG_RUNTIME_DIR/fuse-access-guard.<PID>.sock`)
+    #[arg(long, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
     /// Command and arguments to run under access restrictions
     #[arg(required = true, num_args = 1..)]
     pub command: Vec<String>,
@@ -33,6 +157,25 @@ impl CliArgs {
     pub fn command_args(&self) -> &[String] {
         &self.command[1..]
     }
+
+    /// Whether accesses are recorded to propose a policy
+    pub fn learning(&self) -> bool {
+        self.learn || self.learn_output.is_some()
+    }
+
+    /// Whether deny rules block accesses, rather than only being logged
+    pub fn enforcing(&self) -> bool {
+        self.enforce && !self.dry_run
+    }
+
+    /// Where to serve the control socket, if enabled
+    pub fn control_socket_path(&self) -> Option<PathBuf> {
+        match (&self.control_socket, self.control) {
+            (Some(path), _) => Some(path.clone()),
+            (None, true) => Some(crate::control::default_socket_path()),
+            (None, false) => None,
+        }
+    }
 }
 
 pub fn parse_args() -> CliArgs {
@@ -50,6 +193,143 @@ mod tests {
         assert_eq!(args.command_args(), &["-la"]);
         assert!(!args.quiet);
         assert!(args.log_file.is_none());
+        assert_eq!(args.log_format, LogFormat::Text);
+        assert_eq!(args.audit, AuditLevel::Denied);
+        assert!(!args.verbose);
+        assert!(!args.no_reload);
+        assert!(!args.no_protect_settings);
+        assert!(args.enforcing());
+        assert!(!args.learning());
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--dry-run", "--", "ls"]);
+        assert!(!args.enforcing());
+        let args = CliArgs::parse_from(["fuse-access-guard", "--enforce=false", "--", "ls"]);
+        assert!(!args.enforcing());
+        let args = CliArgs::parse_from(["fuse-access-guard", "--enforce", "true", "--", "ls"]);
+        assert!(args.enforcing());
+    }
+
+    #[test]
+    fn test_parse_learn() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--learn", "--", "ls"]);
+        assert!(args.learning() && args.learn_output.is_none());
+        let args = CliArgs::parse_from(["fuse-access-guard", "--learn-output", "p.json", "--", "ls"]);
+        assert!(args.learning());
+    }
+
+    #[test]
+    fn test_parse_audit_level() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--audit", "sensitive", "--", "ls"]);
+        assert_eq!(args.audit, AuditLevel::Sensitive);
+        let args = CliArgs::parse_from(["fuse-access-guard", "--audit", "all", "--", "ls"]);
+        assert_eq!(args.audit, AuditLevel::All);
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--summary",
+            "--report",
+            "report.json",
+            "--report-format",
+            "json",
+            "--",
+            "ls",
+        ]);
+        assert!(args.summary);
+        assert_eq!(args.report, Some(PathBuf::from("report.json")));
+        assert_eq!(args.report_format, LogFormat::Json);
+        let without_report = ["fuse-access-guard", "--report-format", "json", "--", "ls"];
+        assert!(CliArgs::try_parse_from(without_report).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_limits() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--", "ls"]);
+        assert_eq!(args.log_dedup_window, 1000);
+        assert_eq!(args.log_rate_limit, 100);
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--log-dedup-window",
+            "0",
+            "--log-rate-limit",
+            "5",
+            "--",
+            "ls",
+        ]);
+        assert_eq!(args.log_dedup_window, 0);
+        assert_eq!(args.log_rate_limit, 5);
+    }
+
+    #[test]
+    fn test_parse_log_sinks() {
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--log-sink",
+            "journald",
+            "--log-sink",
+            "file:/tmp/guard.log",
+            "--log-max-size",
+            "4096",
+            "--",
+            "ls",
+        ]);
+        assert_eq!(
+            args.log_sink,
+            vec![SinkSpec::Journald, SinkSpec::File("/tmp/guard.log".into())]
+        );
+        assert_eq!(args.log_max_size, Some(4096));
+        assert!(!args.log_truncate);
+        assert_eq!(args.log_max_files, None);
+        let bad = ["fuse-access-guard", "--log-sink", "kafka", "--", "ls"];
+        assert!(CliArgs::try_parse_from(bad).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_file_options() {
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--log-truncate",
+            "--log-max-files",
+            "5",
+            "--",
+            "ls",
+        ]);
+        assert!(args.log_truncate);
+        assert_eq!(args.log_max_files, Some(5));
+        let zero = ["fuse-access-guard", "--log-max-files", "0", "--", "ls"];
+        assert!(CliArgs::try_parse_from(zero).is_err());
+    }
+
+    #[test]
+    fn test_parse_control_socket() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--", "ls"]);
+        assert_eq!(args.control_socket_path(), None);
+        let args = CliArgs::parse_from(["fuse-access-guard", "--control", "--", "ls"]);
+        let path = args.control_socket_path().unwrap();
+        assert!(path.to_string_lossy().ends_with(".sock"));
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--control-socket",
+            "/tmp/guard.sock",
+            "--",
+            "ls",
+        ]);
+        assert_eq!(args.control_socket_path(), Some(PathBuf::from("/tmp/guard.sock")));
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--log-format", "json", "--", "ls"]);
+        assert_eq!(args.log_format, LogFormat::Json);
+        assert!(
+            CliArgs::try_parse_from(["fuse-access-guard", "--log-format", "xml", "--", "ls"])
+                .is_err()
+        );
     }
 
     #[test]
@@ -83,6 +363,33 @@ mod tests {
         assert_eq!(args.command_args(), &["-la"]);
     }
 
+    #[test]
+    fn test_parse_verbose() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "-v", "--", "ls"]);
+        assert!(args.verbose);
+        assert_eq!(args.command_name(), "ls");
+    }
+
+    #[test]
+    fn test_parse_hide() {
+        let args = CliArgs::parse_from(["fuse-access-guard", "--hide", "--", "ls"]);
+        assert!(args.hide);
+    }
+
+    #[test]
+    fn test_parse_protect_inodes() {
+        let args = CliArgs::parse_from([
+            "fuse-access-guard",
+            "--protect-inodes",
+            "--inode-scan-root",
+            "/home/user",
+            "--",
+            "ls",
+        ]);
+        assert!(args.protect_inodes);
+        assert_eq!(args.inode_scan_root, vec![PathBuf::from("/home/user")]);
+    }
+
     #[test]
     fn test_parse_exclude_exec() {
         let args = CliArgs::parse_from([
@@ -1,10 +1,16 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub permissions: Permissions,
+    /// fuse-access-guard specific settings, ignored by other readers of the file
+    #[serde(default, rename = "accessGuard")]
+    pub access_guard: GuardSettings,
+    /// File the settings were loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -12,6 +18,75 @@ pub struct Permissions {
     pub deny: Vec<String>,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct GuardSettings {
+    /// Deny rules whose matching entries are also hidden from directory
+    /// listings and lookups
+    #[serde(default)]
+    pub hide: Vec<String>,
+    /// Deny rules that only log what they would deny (`WOULD_DENY`)
+    #[serde(default, rename = "auditOnly")]
+    pub audit_only: Vec<String>,
+    /// Paths whose allowed opens are logged with `--audit sensitive`
+    #[serde(default)]
+    pub sensitive: Vec<String>,
+    /// Read rules answered with substitute content instead of `EACCES`
+    #[serde(default)]
+    pub substitute: Vec<SubstituteSettings>,
+    /// Directory holding the files served by `"with": "decoy"` substitutes
+    #[serde(default, rename = "decoyDir")]
+    pub decoy_dir: Option<String>,
+    #[serde(default)]
+    pub log: LogSettings,
+}
+
+/// A rule under `accessGuard.substitute`, e.g.
+/// `{"rule": "Read(./.env)", "with": "stub", "content": "API_KEY=dummy"}`
+#[derive(Debug, Deserialize)]
+pub struct SubstituteSettings {
+    pub rule: String,
+    #[serde(flatten)]
+    pub with: Substitute,
+}
+
+/// Content served in place of a file whose reading is denied
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "with", rename_all = "lowercase")]
+pub enum Substitute {
+    /// An empty file
+    Empty,
+    /// Fixed content
+    Stub { content: String },
+    /// The file of the same name in `decoyDir`, or an empty file if there is
+    /// none
+    Decoy,
+}
+
+impl Substitute {
+    /// Name used in settings and logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Substitute::Empty => "empty",
+            Substitute::Stub { .. } => "stub",
+            Substitute::Decoy => "decoy",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogSettings {
+    /// Log sinks (`stderr`, `file:PATH`, `syslog`, `journald`), used when
+    /// none are given on the command line
+    #[serde(default)]
+    pub sinks: Vec<String>,
+    /// Rotate log files once they would exceed this many bytes
+    #[serde(default, rename = "maxFileSize")]
+    pub max_file_size: Option<u64>,
+    /// Number of rotated log files to keep
+    #[serde(default, rename = "maxFiles")]
+    pub max_files: Option<u32>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error("settings file not found: {0}")]
@@ -22,13 +97,24 @@ pub enum ConfigError {
     ParseError(#[from] serde_json::Error),
 }
 
+/// The settings file of the project in `dir`
+pub fn settings_path(dir: &Path) -> PathBuf {
+    dir.join(".claude").join("settings.json")
+}
+
 pub fn load_settings(dir: &Path) -> Result<Settings, ConfigError> {
-    let path = dir.join(".claude").join("settings.json");
+    let path = settings_path(dir);
     if !path.exists() {
         return Err(ConfigError::NotFound(path.display().to_string()));
     }
     let content = std::fs::read_to_string(&path)?;
-    let settings: Settings = serde_json::from_str(&content)?;
+    parse_settings(&content, &path)
+}
+
+/// Parse the content of the settings file at `path`
+pub fn parse_settings(content: &str, path: &Path) -> Result<Settings, ConfigError> {
+    let mut settings: Settings = serde_json::from_str(content)?;
+    settings.source = Some(path.to_path_buf());
     Ok(settings)
 }
 
@@ -50,9 +136,57 @@ mod tests {
 
         let settings = load_settings(dir.path()).unwrap();
         assert_eq!(settings.permissions.deny.len(), 2);
+        assert!(settings.access_guard.hide.is_empty());
         assert_eq!(settings.permissions.deny[0], "Read(./a.txt)");
     }
 
+    #[test]
+    fn test_load_access_guard_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let claude_dir = dir.path().join(".claude");
+        fs::create_dir_all(&claude_dir).unwrap();
+        fs::write(
+            claude_dir.join("settings.json"),
+            r#"{
+                "permissions": {"deny": []},
+                "accessGuard": {
+                    "hide": ["Read(./.env)"],
+                    "auditOnly": ["Delete(./migrations/**)"],
+                    "sensitive": ["./*.pem"],
+                    "substitute": [
+                        {"rule": "Read(./.env)", "with": "stub", "content": "API_KEY=dummy"},
+                        {"rule": "Read(./*.pem)", "with": "decoy"}
+                    ],
+                    "decoyDir": "./.decoys",
+                    "log": {"sinks": ["stderr", "journald"], "maxFileSize": 1048576, "maxFiles": 3}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let settings = load_settings(dir.path()).unwrap();
+        assert_eq!(settings.access_guard.hide, vec!["Read(./.env)"]);
+        assert_eq!(settings.access_guard.audit_only, vec!["Delete(./migrations/**)"]);
+        assert_eq!(settings.access_guard.sensitive, vec!["./*.pem"]);
+        let substitute = &settings.access_guard.substitute;
+        assert_eq!(substitute[0].rule, "Read(./.env)");
+        assert_eq!(
+            substitute[0].with,
+            Substitute::Stub {
+                content: "API_KEY=dummy".to_string()
+            }
+        );
+        assert_eq!(substitute[1].with, Substitute::Decoy);
+        assert_eq!(settings.access_guard.decoy_dir.as_deref(), Some("./.decoys"));
+        assert_eq!(settings.access_guard.log.sinks, vec!["stderr", "journald"]);
+        assert_eq!(settings.access_guard.log.max_file_size, Some(1048576));
+        assert_eq!(settings.access_guard.log.max_files, Some(3));
+        assert_eq!(
+            settings.source,
+            Some(dir.path().join(".claude").join("settings.json"))
+        );
+    }
+
     #[test]
     fn test_load_missing_file() {
         let dir = tempfile::tempdir().unwrap();
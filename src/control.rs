@@ -0,0 +1,457 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::logger::{LogSink, Record};
+use crate::namespace::{bypassed_mounts, uncovered_mount_points, Mounts};
+use crate::process::mount_namespace;
+use crate::report::Report;
+use crate::rules::SharedRules;
+
+/// Environment variable announcing the control socket to the guarded command
+pub const SOCKET_ENV: &str = "FUSE_ACCESS_GUARD_SOCKET";
+
+/// Size of a file descriptor in SCM_RIGHTS ancillary data
+const FD_SIZE: u32 = std::mem::size_of::<RawFd>() as u32;
+
+/// Event subscribers that stop reading are dropped once a line has been
+/// pending this long
+const STREAM_WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// `$XDG_RUNTIME_DIR/fuse-access-guard.<pid>.sock`, or the same name in the
+/// temporary directory
+pub fn default_socket_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("fuse-access-guard.{}.sock", std::process::id()))
+}
+
+/// Log sink forwarding every line to the clients that sent `events`
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<UnixStream>>>);
+
+impl Subscribers {
+    fn add(&self, stream: UnixStream) {
+        if let Ok(mut clients) = self.0.lock() {
+            clients.push(stream);
+        }
+    }
+}
+
+impl LogSink for Subscribers {
+    fn write(&mut self, record: &Record) {
+        let line = format!("{}\n", record.line);
+        if let Ok(mut clients) = self.0.lock() {
+            clients.retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+        }
+    }
+}
+
+/// Where the FUSE daemon serves the control socket
+pub struct ControlSocket {
+    pub path: PathBuf,
+    /// Directory relative rules are resolved against
+    pub cwd: PathBuf,
+    pub subscribers: Subscribers,
+}
+
+/// One request per line, e.g. `{"cmd":"add_rule","rule":"Read(./.env)"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    Mounts,
+    Rules,
+    Events,
+    Counters,
+    AddRule {
+        rule: String,
+        #[serde(default)]
+        hide: bool,
+    },
+    RemoveRule {
+        rule: String,
+    },
+}
+
+/// Session state inspected and changed through the control socket
+pub struct Control {
+    pub cwd: PathBuf,
+    pub mounts: Arc<Mounts>,
+    pub rules: Arc<SharedRules>,
+    pub report: Arc<Mutex<Report>>,
+    pub subscribers: Subscribers,
+}
+
+/// Bind the socket at `path`, accessible to the current user only. Done
+/// before anything is mounted, which could cover its directory.
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Accept clients of `listener` on a background thread and pass them on to
+/// the FUSE daemon through `daemon`, together with whether they run in
+/// `host_namespace`, the mount namespace fuse-access-guard was started in.
+/// Guarded processes live in the private namespace and can never re-enter
+/// that one, so only clients from it may loosen rules. The daemon itself
+/// runs in a user namespace of its own that cannot inspect host processes,
+/// so this is done by the process that started it.
+pub fn admit(listener: UnixListener, daemon: UnixDatagram, host_namespace: Option<PathBuf>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // Unknown peers are treated as guarded
+            let trusted = host_namespace.is_some()
+                && peer_pid(&stream).is_some_and(|pid| mount_namespace(pid) == host_namespace);
+            if send_client(&daemon, &stream, trusted).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+impl Control {
+    /// Serve the clients passed on by [`admit`] through `clients` on
+    /// background threads
+    pub fn serve(self, clients: UnixDatagram) {
+        let control = Arc::new(self);
+        std::thread::spawn(move || {
+            while let Ok((stream, trusted)) = recv_client(&clients) {
+                let control = Arc::clone(&control);
+                std::thread::spawn(move || control.handle(stream, trusted));
+            }
+        });
+    }
+
+    fn handle(&self, stream: UnixStream, trusted: bool) {
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let mut writer = stream;
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(Request::Events) => {
+                    // The connection turns into a stream of log lines
+                    if writeln!(writer, "{}", json!({ "ok": true })).is_ok()
+                        && writer.set_write_timeout(Some(STREAM_WRITE_TIMEOUT)).is_ok()
+                    {
+                        self.subscribers.add(writer);
+                    }
+                    return;
+                }
+                Ok(request) => self.respond(request, trusted),
+                Err(e) => error(format!("invalid request: {e}")),
+            };
+            if writeln!(writer, "{response}").is_err() {
+                break;
+            }
+        }
+    }
+
+    fn respond(&self, request: Request, trusted: bool) -> serde_json::Value {
+        match request {
+            Request::Mounts => json!({ "ok": true, "mounts": self.mounts.targets() }),
+            Request::Rules => {
+                let rules: Vec<_> = self
+                    .rules
+                    .load()
+                    .rules()
+                    .iter()
+                    .map(|rule| {
+                        json!({
+                            "rule": rule.entry,
+                            "hide": rule.hide,
+                            "source": rule.source,
+                            "implicit": rule.implicit,
+                            "audit_only": rule.audit_only,
+                            "redact": rule.redact,
+                            "substitute": rule.substitute.as_ref().map(|with| with.name()),
+                        })
+                    })
+                    .collect();
+                json!({ "ok": true, "rules": rules })
+            }
+            Request::Counters => match self.report.lock() {
+                Ok(report) => json!({ "ok": true, "counters": report.counters() }),
+                Err(_) => error("counters unavailable".to_string()),
+            },
+            Request::AddRule { rule, hide } => {
+                match self
+                    .rules
+                    .update(|rules| rules.add_rule(&rule, &self.cwd, hide))
+                {
+                    Ok(()) => {
+                        // Mount what the new rule needs, if possible
+                        let rules = self.rules.load();
+                        let mounted = self.mounts.extend(&rules).unwrap_or_default();
+                        let bypassed = bypassed_mounts(&mounted);
+                        let enforced = bypassed.is_empty()
+                            && uncovered_mount_points(&rules, &self.mounts.targets()).is_empty();
+                        json!({
+                            "ok": true,
+                            "enforced": enforced,
+                            "mounted": mounted,
+                            "bypassed": bypassed,
+                        })
+                    }
+                    Err(e) => error(e.to_string()),
+                }
+            }
+            Request::RemoveRule { rule } => {
+                if !trusted {
+                    return error(
+                        "permission denied: guarded processes cannot remove rules".to_string(),
+                    );
+                }
+                let removed = self.rules.update(|rules| rules.remove_rule(&rule));
+                json!({ "ok": true, "removed": removed })
+            }
+            Request::Events => error("unexpected events request".to_string()),
+        }
+    }
+}
+
+fn error(message: String) -> serde_json::Value {
+    json!({ "ok": false, "error": message })
+}
+
+/// Room for one file descriptor in ancillary data, aligned for `cmsghdr`
+type FdBuffer = [u64; 4];
+
+/// Send the connection `client` over `channel` (SCM_RIGHTS), with `trusted`
+/// as the single data byte
+fn send_client(channel: &UnixDatagram, client: &UnixStream, trusted: bool) -> io::Result<()> {
+    let mut byte = [trusted as u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let mut buffer: FdBuffer = [0; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = buffer.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(FD_SIZE) } as _;
+    let ret = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(FD_SIZE) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, client.as_raw_fd());
+        libc::sendmsg(channel.as_raw_fd(), &msg, 0)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive a connection sent by [`send_client`]
+fn recv_client(channel: &UnixDatagram) -> io::Result<(UnixStream, bool)> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let mut buffer: FdBuffer = [0; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = buffer.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of::<FdBuffer>() as _;
+    if unsafe { libc::recvmsg(channel.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no connection received"));
+        }
+        std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd)
+    };
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+    Ok((stream, byte[0] == 1))
+}
+
+/// Pid of the process at the other end of `stream` (SO_PEERCRED)
+fn peer_pid(stream: &UnixStream) -> Option<u32> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (ret == 0).then_some(cred.pid as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GuardSettings, Permissions, Settings};
+    use crate::fs_view::FsView;
+    use crate::logger::{Logger, Severity};
+    use crate::rules::{AccessRules, Operation};
+
+    fn control(dir: &Path) -> Control {
+        let settings = Settings {
+            permissions: Permissions {
+                deny: vec!["Read(./.env)".to_string()],
+            },
+            access_guard: GuardSettings::default(),
+            source: None,
+        };
+        let rules = Arc::new(SharedRules::new(
+            AccessRules::new(&settings, dir, vec![], FsView::default()).unwrap(),
+        ));
+        let report = Arc::new(Mutex::new(Report::new(false, None)));
+        let logger = Arc::new(Mutex::new(Logger::new(Vec::new())));
+        let fs = FsView::default();
+        let mounts = Mounts::new(0, Arc::clone(&rules), logger, Arc::clone(&report), fs);
+        mounts.assume_mounted(dir.to_path_buf());
+        Control {
+            cwd: dir.to_path_buf(),
+            mounts: Arc::new(mounts),
+            rules,
+            report,
+            subscribers: Subscribers::default(),
+        }
+    }
+
+    /// Serve `control` at `path` as if clients were admitted by a process in
+    /// `host_namespace`
+    fn serve(control: Control, path: &Path, host_namespace: Option<PathBuf>) {
+        let (gate, clients) = UnixDatagram::pair().unwrap();
+        admit(bind(path).unwrap(), gate, host_namespace);
+        control.serve(clients);
+    }
+
+    /// Send one request line and read one response line
+    fn request(stream: &mut BufReader<UnixStream>, line: &str) -> serde_json::Value {
+        writeln!(stream.get_mut(), "{line}").unwrap();
+        let mut response = String::new();
+        stream.read_line(&mut response).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn connect(path: &Path) -> BufReader<UnixStream> {
+        BufReader::new(UnixStream::connect(path).unwrap())
+    }
+
+    #[test]
+    fn test_inspect_and_change_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = std::fs::canonicalize(dir.path()).unwrap();
+        let path = cwd.join("control.sock");
+        let control = control(&cwd);
+        let rules = Arc::clone(&control.rules);
+        serve(control, &path, mount_namespace(std::process::id()));
+
+        let mut client = connect(&path);
+        let mounts = request(&mut client, r#"{"cmd":"mounts"}"#);
+        assert_eq!(mounts["mounts"], json!([cwd]));
+        let listed = request(&mut client, r#"{"cmd":"rules"}"#);
+        assert_eq!(listed["rules"][0]["rule"], "Read(./.env)");
+
+        let added = request(
+            &mut client,
+            r#"{"cmd":"add_rule","rule":"Write(./*.lock)"}"#,
+        );
+        assert_eq!(
+            added,
+            json!({ "ok": true, "enforced": true, "mounted": [], "bypassed": [] })
+        );
+        assert!(rules
+            .load()
+            .is_denied(&cwd.join("Cargo.lock"), Operation::Write));
+
+        let removed = request(
+            &mut client,
+            r#"{"cmd":"remove_rule","rule":"Read(./.env)"}"#,
+        );
+        assert_eq!(removed, json!({ "ok": true, "removed": 1 }));
+        assert!(!rules.load().is_denied(&cwd.join(".env"), Operation::Read));
+
+        let counters = request(&mut client, r#"{"cmd":"counters"}"#);
+        assert_eq!(counters["counters"]["denied"], json!([]));
+        let bad = request(&mut client, r#"{"cmd":"reboot"}"#);
+        assert_eq!(bad["ok"], false);
+        let bad_rule = request(&mut client, r#"{"cmd":"add_rule","rule":"Bogus(./x)"}"#);
+        assert_eq!(bad_rule["ok"], false);
+    }
+
+    #[test]
+    fn test_guarded_peer_cannot_remove_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        // No peer shares this namespace, as for the guarded command
+        let control = control(dir.path());
+        let rules = Arc::clone(&control.rules);
+        serve(control, &path, Some(PathBuf::from("mnt:[0]")));
+
+        let mut client = connect(&path);
+        let removed = request(
+            &mut client,
+            r#"{"cmd":"remove_rule","rule":"Read(./.env)"}"#,
+        );
+        assert_eq!(removed["ok"], false);
+        assert_eq!(rules.load().rules().len(), 1);
+        let added = request(&mut client, r#"{"cmd":"add_rule","rule":"Read(./id_rsa)"}"#);
+        assert_eq!(added["ok"], true);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let control = control(dir.path());
+        let mut subscribers = control.subscribers.clone();
+        serve(control, &path, None);
+
+        let mut client = connect(&path);
+        assert_eq!(request(&mut client, r#"{"cmd":"events"}"#)["ok"], true);
+        while subscribers.0.lock().unwrap().is_empty() {
+            std::thread::yield_now();
+        }
+        subscribers.write(&Record {
+            line: "[DENIED] op=read path=/p/.env".to_string(),
+            severity: Severity::Warning,
+            fields: Vec::new(),
+        });
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line, "[DENIED] op=read path=/p/.env\n");
+
+        drop(client);
+        for _ in 0..2 {
+            subscribers.write(&Record {
+                line: "gone".to_string(),
+                severity: Severity::Info,
+                fields: Vec::new(),
+            });
+        }
+        assert!(subscribers.0.lock().unwrap().is_empty());
+    }
+}
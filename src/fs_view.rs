@@ -0,0 +1,151 @@
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use nix::fcntl::{openat, OFlag};
+use nix::sys::stat::Mode;
+
+/// How rules reach the files they name. Outside the FUSE daemon paths are
+/// used as they are. Inside it they would be served by the daemon's own
+/// mounts, subject to its own rules and with inode numbers of their own, so
+/// paths below a mount are opened relative to the directory that was open
+/// underneath it before mounting. Clones share the mounts added.
+#[derive(Debug, Clone, Default)]
+pub struct FsView {
+    sources: Arc<RwLock<Vec<Source>>>,
+}
+
+/// A mount target and the directory that was open underneath it
+type Source = (PathBuf, Arc<File>);
+
+/// A path naming the same file as the one resolved, valid while `fd` is open
+struct Resolved {
+    path: PathBuf,
+    fd: Option<OwnedFd>,
+}
+
+impl FsView {
+    /// Reach paths below `target` through `source`, the directory that is
+    /// mounted over
+    pub fn add_source(&self, target: &Path, source: File) {
+        let mut sources = self.sources.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        sources.push((target.to_path_buf(), Arc::new(source)));
+    }
+
+    /// Metadata of `path`, following symlinks
+    pub fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.stat(path, true)
+    }
+
+    /// Metadata of `path` itself
+    pub fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.stat(path, false)
+    }
+
+    /// Paths of the entries of the directory `path`
+    pub fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let resolved = self.resolve(path, true)?;
+        std::fs::read_dir(&resolved.path)?
+            .map(|entry| Ok(path.join(entry?.file_name())))
+            .collect()
+    }
+
+    /// Content of the file `path`
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(self.resolve(path, true)?.path)
+    }
+
+    /// Absolute path of `path` with symlinks resolved, like realpath
+    pub fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let resolved = self.resolve(path, true)?;
+        match resolved.fd {
+            Some(_) => std::fs::read_link(&resolved.path),
+            None => std::fs::canonicalize(path),
+        }
+    }
+
+    fn stat(&self, path: &Path, follow: bool) -> io::Result<Metadata> {
+        match self.resolve(path, follow)? {
+            Resolved { fd: Some(fd), .. } => File::from(fd).metadata(),
+            Resolved { path, fd: None } if follow => std::fs::metadata(path),
+            Resolved { path, fd: None } => std::fs::symlink_metadata(path),
+        }
+    }
+
+    /// Open `path` below the deepest mount containing it, if any, without
+    /// going through the mount
+    fn resolve(&self, path: &Path, follow: bool) -> io::Result<Resolved> {
+        let source = {
+            let sources = self.sources.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            sources
+                .iter()
+                .filter(|(target, _)| path.starts_with(target))
+                .max_by_key(|(target, _)| target.components().count())
+                .map(|(target, source)| (target.clone(), Arc::clone(source)))
+        };
+        let Some((target, source)) = source else {
+            return Ok(Resolved {
+                path: path.to_path_buf(),
+                fd: None,
+            });
+        };
+
+        let rel = path.strip_prefix(&target).unwrap_or(path);
+        let rel = if rel.as_os_str().is_empty() { Path::new(".") } else { rel };
+        let mut flags = OFlag::O_PATH | OFlag::O_CLOEXEC;
+        if !follow {
+            flags |= OFlag::O_NOFOLLOW;
+        }
+        let fd = openat(Some(source.as_raw_fd()), rel, flags, Mode::empty())?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Resolved {
+            path: PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd())),
+            fd: Some(fd),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_paths_below_a_source_resolve_through_it() {
+        let real = tempfile::tempdir().unwrap();
+        std::fs::create_dir(real.path().join("sub")).unwrap();
+        std::fs::write(real.path().join("sub/.env"), "KEY=1").unwrap();
+        std::os::unix::fs::symlink(".env", real.path().join("sub/link")).unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+
+        // Paths below the target reach the real directory, as if it had
+        // been mounted over
+        let view = FsView::default();
+        let target = Path::new("/nonexistent-mount");
+        view.add_source(target, File::open(real.path()).unwrap());
+        let env = target.join("sub/.env");
+
+        let meta = view.metadata(&env).unwrap();
+        let real_meta = std::fs::metadata(real.path().join("sub/.env")).unwrap();
+        assert_eq!((meta.dev(), meta.ino()), (real_meta.dev(), real_meta.ino()));
+        assert!(view.symlink_metadata(&target.join("sub/link")).unwrap().is_symlink());
+        assert_eq!(view.metadata(&target.join("sub/link")).unwrap().ino(), real_meta.ino());
+        assert_eq!(view.read(&env).unwrap(), b"KEY=1");
+        assert_eq!(
+            view.canonicalize(&target.join("sub/link")).unwrap(),
+            std::fs::canonicalize(real.path().join("sub/.env")).unwrap()
+        );
+        let mut entries = view.read_dir(&target.join("sub")).unwrap();
+        entries.sort();
+        assert_eq!(entries, [target.join("sub/.env"), target.join("sub/link")]);
+        assert!(view.metadata(&target.join("missing")).is_err());
+
+        // Other paths are used as they are
+        let other = elsewhere.path().join("file");
+        std::fs::write(&other, "x").unwrap();
+        assert_eq!(view.read(&other).unwrap(), b"x");
+        assert_eq!(view.clone().metadata(&env).unwrap().ino(), real_meta.ino());
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::fs_view::FsView;
+use crate::logger::operation_name;
+use crate::rules::{canonicalize_rule_path, Operation};
+
+/// Operations that change the file system
+const MODIFYING: [Operation; 5] = [
+    Operation::Write,
+    Operation::Create,
+    Operation::Delete,
+    Operation::Rename,
+    Operation::Overwrite,
+];
+
+/// Records the accesses of the guarded command below the project directory
+/// (`--learn`) and proposes a policy from them when it exits
+pub struct Learner {
+    root: PathBuf,
+    /// Path relative to `root` -> operations; empty for entries only seen in
+    /// directory listings
+    accesses: BTreeMap<PathBuf, BTreeSet<Operation>>,
+    /// Where to write the policy; stderr if unset
+    output: Option<File>,
+}
+
+/// Recorded paths proposed as one rule path, e.g. `./src/**`
+struct Group {
+    pattern: String,
+    operations: BTreeSet<Operation>,
+    paths: usize,
+}
+
+impl Learner {
+    /// Record accesses below `root`. Created before anything is mounted.
+    pub fn new(root: &Path, output: Option<File>) -> Self {
+        Learner {
+            root: canonicalize_rule_path(root, &FsView::default()),
+            accesses: BTreeMap::new(),
+            output,
+        }
+    }
+
+    /// Record an allowed `op` on `path`
+    pub fn record(&mut self, path: &Path, op: Operation) {
+        if let Some(rel) = self.relative(path) {
+            self.accesses.entry(rel).or_default().insert(op);
+        }
+    }
+
+    /// Record that `path` exists, as seen in a directory listing
+    pub fn record_seen(&mut self, path: &Path) {
+        if let Some(rel) = self.relative(path) {
+            self.accesses.entry(rel).or_default();
+        }
+    }
+
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        (!rel.as_os_str().is_empty()).then(|| rel.to_path_buf())
+    }
+
+    /// Print or write the suggested policy
+    pub fn finish(&mut self) {
+        let content = match serde_json::to_string_pretty(&self.policy()) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+        match self.output {
+            Some(ref mut file) => {
+                let _ = writeln!(file, "{content}");
+            }
+            None => eprintln!("fuse-access-guard: suggested policy:\n{content}"),
+        }
+    }
+
+    /// A settings.json fragment denying writes to what was only read and
+    /// reads of secret-looking files, followed by what was observed. Secrets
+    /// the command did read are proposed as audit-only rules, as denying
+    /// them would break it.
+    pub fn policy(&self) -> serde_json::Value {
+        let mut deny = Vec::new();
+        let mut audit_only = Vec::new();
+        let mut secrets = Vec::new();
+        for (rel, operations) in self.accesses.iter().filter(|(rel, _)| looks_secret(rel)) {
+            let rule = format!("Read(./{})", rel.display());
+            if operations.contains(&Operation::Read) || operations.contains(&Operation::Execute) {
+                audit_only.push(rule);
+            } else {
+                deny.push(rule);
+            }
+            secrets.push(json!({
+                "path": format!("./{}", rel.display()),
+                "operations": names(operations),
+            }));
+        }
+
+        let groups = self.groups();
+        for group in &groups {
+            if !MODIFYING.iter().any(|op| group.operations.contains(op)) {
+                deny.push(format!("Write({})", group.pattern));
+            }
+        }
+        let accesses: Vec<_> = groups
+            .iter()
+            .map(|group| {
+                json!({
+                    "path": group.pattern,
+                    "operations": names(&group.operations),
+                    "paths": group.paths,
+                })
+            })
+            .collect();
+
+        json!({
+            "permissions": { "deny": deny },
+            "accessGuard": { "auditOnly": audit_only },
+            "learned": { "accesses": accesses, "secrets": secrets },
+        })
+    }
+
+    /// Collapse the recorded paths other than secrets: everything inside a
+    /// top-level directory into `./dir/**`, and top-level files with the
+    /// same extension and operations into `./*.ext`
+    fn groups(&self) -> Vec<Group> {
+        let accesses: Vec<_> = self
+            .accesses
+            .iter()
+            .filter(|(rel, _)| !looks_secret(rel))
+            .collect();
+        let first = |rel: &Path| {
+            rel.components()
+                .next()
+                .map(|c| c.as_os_str().to_os_string())
+        };
+        let dirs: BTreeSet<_> = accesses
+            .iter()
+            .filter(|(rel, ops)| rel.components().count() > 1 || ops.contains(&Operation::List))
+            .filter_map(|(rel, _)| first(rel))
+            .collect();
+
+        let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+        let mut files: BTreeMap<(String, &BTreeSet<Operation>), Vec<&PathBuf>> = BTreeMap::new();
+        for &(rel, operations) in &accesses {
+            let top = first(rel).unwrap_or_default();
+            let pattern = if dirs.contains(&top) {
+                format!("./{}/**", top.to_string_lossy())
+            } else if let Some(ext) = rel.extension() {
+                let key = (ext.to_string_lossy().into_owned(), operations);
+                files.entry(key).or_default().push(rel);
+                continue;
+            } else {
+                format!("./{}", rel.display())
+            };
+            add(&mut groups, pattern, operations, 1);
+        }
+        for ((ext, operations), paths) in files {
+            if paths.len() > 1 {
+                add(&mut groups, format!("./*.{ext}"), operations, paths.len());
+            } else {
+                add(
+                    &mut groups,
+                    format!("./{}", paths[0].display()),
+                    operations,
+                    1,
+                );
+            }
+        }
+        groups.into_values().collect()
+    }
+}
+
+fn add(
+    groups: &mut BTreeMap<String, Group>,
+    pattern: String,
+    ops: &BTreeSet<Operation>,
+    paths: usize,
+) {
+    let group = groups.entry(pattern.clone()).or_insert(Group {
+        pattern,
+        operations: BTreeSet::new(),
+        paths: 0,
+    });
+    group.operations.extend(ops);
+    group.paths += paths;
+}
+
+fn names(operations: &BTreeSet<Operation>) -> Vec<&'static str> {
+    operations.iter().map(|&op| operation_name(op)).collect()
+}
+
+/// Returns true if the file name of `path` suggests it holds credentials
+pub fn looks_secret(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    const NAMES: [&str; 6] = [
+        ".netrc",
+        ".npmrc",
+        ".pypirc",
+        ".pgpass",
+        ".htpasswd",
+        ".git-credentials",
+    ];
+    const PREFIXES: [&str; 6] = [
+        ".env",
+        "id_rsa",
+        "id_ecdsa",
+        "id_ed25519",
+        "credentials",
+        "secrets",
+    ];
+    const EXTENSIONS: [&str; 8] = [
+        "pem", "key", "p12", "pfx", "jks", "keystore", "secret", "kdbx",
+    ];
+    if name.ends_with(".pub") || name == ".env.example" || name == ".env.sample" {
+        return false;
+    }
+    NAMES.contains(&name.as_str())
+        || PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+        || EXTENSIONS
+            .iter()
+            .any(|ext| name.rsplit_once('.').is_some_and(|(_, e)| e == *ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn learner(accesses: &[(&str, &[Operation])]) -> Learner {
+        let mut learner = Learner::new(Path::new("/p"), None);
+        for (path, ops) in accesses {
+            let path = Path::new("/p").join(path);
+            learner.record_seen(&path);
+            for &op in *ops {
+                learner.record(&path, op);
+            }
+        }
+        learner
+    }
+
+    #[test]
+    fn test_looks_secret() {
+        for name in [
+            ".env",
+            ".env.local",
+            "id_rsa",
+            "server.pem",
+            "config/credentials.json",
+            ".npmrc",
+            "prod.KEY",
+        ] {
+            assert!(looks_secret(Path::new(name)), "{name}");
+        }
+        for name in [
+            "id_rsa.pub",
+            ".env.example",
+            "keys.rs",
+            "src/main.rs",
+            "monkey",
+        ] {
+            assert!(!looks_secret(Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_policy_collapses_paths() {
+        use Operation::*;
+        let mut learner = learner(&[
+            ("src/main.rs", &[Stat, Read]),
+            ("src/cli/args.rs", &[Read]),
+            ("src", &[List]),
+            ("target/debug/app", &[Create, Write]),
+            ("Cargo.toml", &[Read]),
+            ("README.md", &[Read]),
+            ("NOTES.md", &[Read]),
+            ("build.log", &[Write]),
+            ("Makefile", &[Stat]),
+            (".env", &[Read]),
+            ("keys/deploy.pem", &[]),
+        ]);
+        learner.record(Path::new("/elsewhere/file"), Read);
+
+        let policy = learner.policy();
+        assert_eq!(
+            policy["permissions"]["deny"],
+            json!([
+                "Read(./keys/deploy.pem)",
+                "Write(./*.md)",
+                "Write(./Cargo.toml)",
+                "Write(./Makefile)",
+                "Write(./src/**)"
+            ])
+        );
+        assert_eq!(policy["accessGuard"]["auditOnly"], json!(["Read(./.env)"]));
+        let accesses = policy["learned"]["accesses"].as_array().unwrap();
+        let src = accesses.iter().find(|a| a["path"] == "./src/**").unwrap();
+        assert_eq!(src["operations"], json!(["read", "stat", "list"]));
+        assert_eq!(src["paths"], 3);
+        let target = accesses
+            .iter()
+            .find(|a| a["path"] == "./target/**")
+            .unwrap();
+        assert_eq!(target["operations"], json!(["write", "create"]));
+        assert!(accesses.iter().any(|a| a["path"] == "./build.log"));
+        assert!(!accesses.iter().any(|a| a["path"] == "./keys/**"));
+        assert_eq!(policy["learned"]["secrets"][0]["path"], "./.env");
+        assert_eq!(policy["learned"]["secrets"][1]["operations"], json!([]));
+    }
+
+    #[test]
+    fn test_policy_written_to_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        let mut learner = Learner::new(Path::new("/p"), Some(File::create(&path).unwrap()));
+        learner.record(Path::new("/p/Cargo.toml"), Operation::Read);
+        learner.finish();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let policy: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            policy["permissions"]["deny"],
+            json!(["Write(./Cargo.toml)"])
+        );
+    }
+}
@@ -1,40 +1,1082 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::time::SystemTime;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::rules::Operation;
+use nix::fcntl::{openat, renameat, AtFlags, OFlag};
+use nix::sys::stat::{fstatat, Mode};
+use serde_json::json;
 
-pub struct Logger {
+use crate::process::ProcessInfo;
+use crate::rules::{DenyRule, Operation};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `[DENIED] ... key=value` lines
+    #[default]
+    Text,
+    /// One JSON object per line (JSON Lines)
+    Json,
+}
+
+/// Identifier used in syslog and journal entries
+const IDENTIFIER: &str = "fuse-access-guard";
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// syslog facility LOG_AUTHPRIV, for security-relevant messages
+const SYSLOG_FACILITY: u8 = 10;
+
+/// How often the background writer checks for expired dedup and rate windows
+const WRITER_TICK: Duration = Duration::from_millis(100);
+
+/// Length of the window `rate_limit` applies to
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Access events queued for the background writer beyond this many are
+/// dropped and counted as suppressed
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Outcome of an access check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Decision {
+    Deny,
+    /// Logged only for audited paths (`--audit`)
+    Allow,
+    /// A deny rule matched but is not enforced (`--dry-run`, `auditOnly`)
+    WouldDeny,
+    /// Reading was denied and answered with substitute content
+    /// (`accessGuard.substitute`)
+    Substitute,
+}
+
+/// A checked filesystem operation
+pub struct AccessEvent<'a> {
+    pub process: &'a ProcessInfo,
+    pub path: &'a str,
+    pub op: Operation,
+    pub decision: Decision,
+    /// The deny rule responsible, when it can be attributed to one
+    pub rule: Option<&'a DenyRule>,
+    /// Why the access was denied when not by a rule, e.g.
+    /// `secret:private-key` for content detected by `--scan-secrets`
+    pub reason: Option<&'a str>,
+    /// Error returned to the caller, for denials
+    pub errno: Option<i32>,
+}
+
+/// An audited file handle being closed
+pub struct CloseEvent<'a> {
+    /// The process that opened the file
+    pub process: &'a ProcessInfo,
+    pub path: &'a str,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// The settings file was re-read while the command runs
+pub struct ReloadEvent<'a> {
+    pub source: &'a Path,
+    /// Rules as written, see `AccessRules::diff`
+    pub added: &'a [String],
+    pub removed: &'a [String],
+    /// Rules dropped from the file that stay in effect until restart
+    pub refused: &'a [String],
+    /// Directories newly mounted for the added rules
+    pub mounted: &'a [PathBuf],
+    /// Why the file could not be applied; the previous rules stay in effect
+    pub error: Option<&'a str>,
+}
+
+/// Importance of a log line, as a syslog severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Reads of substitute content, which suggest an exfiltration attempt
+    Alert,
+    /// Denials
+    Warning,
+    /// Audit events and summaries
+    Info,
+}
+
+impl Severity {
+    fn code(self) -> u8 {
+        match self {
+            Severity::Alert => 1,
+            Severity::Warning => 4,
+            Severity::Info => 6,
+        }
+    }
+}
+
+/// A formatted log line together with structured fields for sinks that
+/// keep them (journald)
+pub struct Record {
+    /// The line in the configured format, without trailing newline
+    pub line: String,
+    pub severity: Severity,
+    /// Journal field names (upper case) and values
+    pub fields: Vec<(&'static str, String)>,
+}
+
+/// Destination for log records
+pub trait LogSink: Send {
+    fn write(&mut self, record: &Record);
+}
+
+/// A sink as named on the command line or in settings: `stderr`,
+/// `file:PATH`, `syslog` or `journald`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkSpec {
+    Stderr,
+    File(PathBuf),
+    Syslog,
+    Journald,
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(SinkSpec::Stderr),
+            "syslog" => Ok(SinkSpec::Syslog),
+            "journald" => Ok(SinkSpec::Journald),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(SinkSpec::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "invalid log sink: {s} (expected stderr, file:PATH, syslog or journald)"
+                )),
+            },
+        }
+    }
+}
+
+/// Choose sinks: those given on the command line, else those from settings,
+/// else stderr. `--log-file` adds a file sink and `--quiet` drops stderr.
+pub fn select_sinks(
+    requested: &[SinkSpec],
+    configured: &[SinkSpec],
+    log_file: Option<&Path>,
     quiet: bool,
-    log_file: Option<File>,
+) -> Vec<SinkSpec> {
+    let mut specs = if !requested.is_empty() {
+        requested.to_vec()
+    } else if !configured.is_empty() {
+        configured.to_vec()
+    } else {
+        vec![SinkSpec::Stderr]
+    };
+    if let Some(path) = log_file {
+        specs.push(SinkSpec::File(path.to_path_buf()));
+    }
+    if quiet {
+        specs.retain(|spec| *spec != SinkSpec::Stderr);
+    }
+    specs
+}
+
+/// How log files are opened and rotated
+#[derive(Debug, Clone, Copy)]
+pub struct FileOptions {
+    /// Empty existing log files instead of appending to them
+    pub truncate: bool,
+    /// Rotate once a file would exceed this many bytes
+    pub max_size: Option<u64>,
+    /// Rotated files to keep (`<PATH>.1` is the newest)
+    pub max_files: usize,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            truncate: false,
+            max_size: None,
+            max_files: 1,
+        }
+    }
+}
+
+pub fn open_sinks(
+    specs: &[SinkSpec],
+    file_options: FileOptions,
+) -> std::io::Result<Vec<Box<dyn LogSink>>> {
+    let mut sinks: Vec<Box<dyn LogSink>> = Vec::new();
+    for spec in specs {
+        match spec {
+            SinkSpec::Stderr => sinks.push(Box::new(StderrSink)),
+            SinkSpec::File(path) => sinks.push(Box::new(FileSink::open(path, file_options)?)),
+            SinkSpec::Syslog => sinks.push(Box::new(SyslogSink::connect(SYSLOG_SOCKET)?)),
+            SinkSpec::Journald => sinks.push(Box::new(JournaldSink::connect(JOURNALD_SOCKET)?)),
+        }
+    }
+    Ok(sinks)
+}
+
+struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn write(&mut self, record: &Record) {
+        eprintln!("{}", record.line);
+    }
+}
+
+/// Appends to a file shared with other guarded sessions. Every line is
+/// written with a single append while holding an exclusive `flock`, and
+/// rotation happens under the same lock.
+struct FileSink {
+    /// Directory holding the log, opened before the FUSE mounts so that
+    /// reopening and rotating never go through them (the daemon must not
+    /// wait on its own mount)
+    dir: File,
+    name: PathBuf,
+    file: File,
+    options: FileOptions,
+}
+
+impl FileSink {
+    fn open(path: &Path, options: FileOptions) -> std::io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not a file path: {}", path.display()),
+            )
+        })?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = File::open(parent)?;
+        let file = open_append(&dir, Path::new(name))?;
+        let mut sink = FileSink {
+            dir,
+            name: PathBuf::from(name),
+            file,
+            options,
+        };
+        if options.truncate {
+            sink.lock()?;
+            let result = sink.file.set_len(0);
+            unlock(&sink.file);
+            result?;
+        }
+        Ok(sink)
+    }
+
+    /// Lock the file currently named `name`, reopening it first if another
+    /// session rotated it away
+    fn lock(&mut self) -> std::io::Result<()> {
+        loop {
+            let ret = unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_EX) };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let ours = self.file.metadata()?;
+            let current = fstatat(Some(self.dir.as_raw_fd()), &self.name, AtFlags::empty());
+            if current.is_ok_and(|st| st.st_dev == ours.dev() && st.st_ino == ours.ino()) {
+                return Ok(());
+            }
+            unlock(&self.file);
+            self.file = open_append(&self.dir, &self.name)?;
+        }
+    }
+
+    /// Shift `<name>.N` to `<name>.N+1`, dropping the oldest, and start a
+    /// new file. Called with the lock held.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let dir = Some(self.dir.as_raw_fd());
+        let max_files = self.options.max_files.max(1);
+        let numbered = |n: usize| {
+            let mut name = self.name.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        for n in (1..max_files).rev() {
+            match renameat(dir, &numbered(n), dir, &numbered(n + 1)) {
+                Err(nix::errno::Errno::ENOENT) | Ok(()) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        renameat(dir, &self.name, dir, &numbered(1))?;
+        let previous = std::mem::replace(&mut self.file, open_append(&self.dir, &self.name)?);
+        unlock(&previous);
+        self.lock()
+    }
+
+    fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        self.lock()?;
+        let result = (|| {
+            let len = line.len() as u64;
+            if let Some(max) = self.options.max_size {
+                // Other sessions may fill the new file before we lock it
+                loop {
+                    let size = self.file.metadata()?.len();
+                    if size == 0 || size + len <= max {
+                        break;
+                    }
+                    self.rotate()?;
+                }
+            }
+            self.file.write_all(line)
+        })();
+        unlock(&self.file);
+        result
+    }
+}
+
+impl LogSink for FileSink {
+    fn write(&mut self, record: &Record) {
+        let _ = self.append(format!("{}\n", record.line).as_bytes());
+    }
+}
+
+fn open_append(dir: &File, name: &Path) -> std::io::Result<File> {
+    let flags = OFlag::O_WRONLY | OFlag::O_APPEND | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+    let fd = openat(Some(dir.as_raw_fd()), name, flags, Mode::from_bits_truncate(0o644))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn unlock(file: &File) {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+}
+
+/// Sends RFC 3164 style messages to the local syslog socket
+struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl SyslogSink {
+    fn connect(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(SyslogSink {
+            socket: datagram_socket(path.as_ref())?,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+}
+
+impl LogSink for SyslogSink {
+    fn write(&mut self, record: &Record) {
+        let _ = self
+            .socket
+            .send_to(syslog_message(record).as_bytes(), &self.path);
+    }
+}
+
+/// Sends entries with structured fields over the journald native protocol
+struct JournaldSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldSink {
+    fn connect(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(JournaldSink {
+            socket: datagram_socket(path.as_ref())?,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+}
+
+impl LogSink for JournaldSink {
+    fn write(&mut self, record: &Record) {
+        let _ = self.socket.send_to(&journal_payload(record), &self.path);
+    }
+}
+
+/// An unbound datagram socket for sending to `path`, failing early if
+/// nothing listens there. Sinks use `send_to`, so a restarted daemon is
+/// picked up again.
+fn datagram_socket(path: &Path) -> std::io::Result<UnixDatagram> {
+    let probe = UnixDatagram::unbound()?;
+    probe
+        .connect(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    UnixDatagram::unbound()
+}
+
+fn syslog_message(record: &Record) -> String {
+    let priority = SYSLOG_FACILITY * 8 + record.severity.code();
+    format!(
+        "<{priority}>{IDENTIFIER}[{}]: {}",
+        std::process::id(),
+        record.line
+    )
+}
+
+/// Serialize a record in the journal native protocol: `NAME=value` lines,
+/// or for values containing newlines, the name, a little-endian 64-bit
+/// length and the raw value
+fn journal_payload(record: &Record) -> Vec<u8> {
+    let priority = record.severity.code().to_string();
+    let fixed = [
+        ("MESSAGE", record.line.as_str()),
+        ("PRIORITY", priority.as_str()),
+        ("SYSLOG_IDENTIFIER", IDENTIFIER),
+    ];
+    let fields = record
+        .fields
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()));
+
+    let mut payload = Vec::new();
+    for (name, value) in fixed.into_iter().chain(fields) {
+        payload.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            payload.extend_from_slice(value.as_bytes());
+        } else {
+            payload.push(b'=');
+            payload.extend_from_slice(value.as_bytes());
+        }
+        payload.push(b'\n');
+    }
+    payload
+}
+
+/// Flood control for access events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogLimits {
+    /// Identical (pid, path, op, decision) events within this window are
+    /// logged once, followed by a repeat count. Zero disables coalescing.
+    pub dedup_window: Duration,
+    /// Maximum access event lines per second; the excess is counted and
+    /// reported in a single notice. Zero means unlimited. Close and reload
+    /// records are not limited.
+    pub rate_limit: u32,
+}
+
+/// Identity of an access event for deduplication
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    pid: u32,
+    path: String,
+    op: Operation,
+    decision: Decision,
+}
+
+/// A log record on its way to the writer
+struct Entry {
+    /// Set for access events, which may be coalesced and rate limited
+    key: Option<EventKey>,
+    /// None for a repeat of an access event already sent
+    record: Option<Record>,
+}
+
+pub struct Logger {
+    format: LogFormat,
+    dedup_window: Duration,
+    /// Start of the dedup window of each access event sent recently, to
+    /// tell repeats apart before they are formatted
+    seen: HashMap<EventKey, Instant>,
+    /// When `seen` was last cleared of closed windows
+    pruned: Option<Instant>,
+    /// Access events dropped because the background writer fell behind
+    dropped: Arc<AtomicU64>,
+    /// Writes lines on the caller's thread until `spawn_writer`
+    writer: Option<LogWriter>,
+    background: Option<(mpsc::SyncSender<Entry>, JoinHandle<LogWriter>)>,
 }
 
 impl Logger {
-    pub fn new(quiet: bool, log_file: Option<File>) -> Self {
-        Logger { quiet, log_file }
+    pub fn new(sinks: Vec<Box<dyn LogSink>>) -> Self {
+        let dropped = Arc::new(AtomicU64::new(0));
+        Logger {
+            format: LogFormat::default(),
+            dedup_window: Duration::ZERO,
+            seen: HashMap::new(),
+            pruned: None,
+            dropped: Arc::clone(&dropped),
+            writer: Some(LogWriter {
+                sinks,
+                format: LogFormat::default(),
+                limits: LogLimits::default(),
+                recent: HashMap::new(),
+                rate_start: None,
+                rate_count: 0,
+                suppressed: 0,
+                dropped,
+            }),
+            background: None,
+        }
     }
 
-    pub fn log_denied(&mut self, pid: u32, process_name: &str, path: &str, op: Operation) {
-        let timestamp = humanize_timestamp(SystemTime::now());
-        let op_str = match op {
-            Operation::Read => "read",
-            Operation::Write => "write",
-            Operation::Execute => "execute",
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        if let Some(ref mut writer) = self.writer {
+            writer.format = format;
+        }
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LogLimits) -> Self {
+        self.dedup_window = limits.dedup_window;
+        if let Some(ref mut writer) = self.writer {
+            writer.limits = limits;
+        }
+        self
+    }
+
+    /// Move log I/O to a background thread so callers only format and
+    /// enqueue lines. Must be called after the last fork.
+    pub fn spawn_writer(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+            let handle = std::thread::spawn(move || writer.run(receiver));
+            self.background = Some((sender, handle));
+        }
+    }
+
+    /// Stop the background writer, if any, and write out pending repeat
+    /// counts and suppression notices
+    pub fn flush(&mut self) {
+        if let Some((sender, handle)) = self.background.take() {
+            drop(sender);
+            if let Ok(writer) = handle.join() {
+                self.writer = Some(writer);
+            }
+        }
+        if let Some(ref mut writer) = self.writer {
+            writer.flush();
+        }
+    }
+
+    /// Returns true if an access event identical to this one was logged
+    /// within the dedup window, counting it as a repeat. Callers can then
+    /// skip resolving the process for an event that would not be written.
+    pub fn coalesce(&mut self, pid: u32, path: &str, op: Operation, decision: Decision) -> bool {
+        let key = EventKey {
+            pid,
+            path: path.to_string(),
+            op,
+            decision,
+        };
+        if !self.is_repeat(&key, Instant::now()) {
+            return false;
+        }
+        self.send(Entry {
+            key: Some(key),
+            record: None,
+        });
+        true
+    }
+
+    fn is_repeat(&self, key: &EventKey, now: Instant) -> bool {
+        self.seen
+            .get(key)
+            .is_some_and(|start| now.duration_since(*start) < self.dedup_window)
+    }
+
+    /// Open a dedup window for `key` unless one is open
+    fn remember(&mut self, key: &EventKey, now: Instant) {
+        if self.dedup_window.is_zero() || self.is_repeat(key, now) {
+            return;
+        }
+        let window = self.dedup_window;
+        if self.pruned.is_none_or(|pruned| now.duration_since(pruned) >= window) {
+            self.seen.retain(|_, start| now.duration_since(*start) < window);
+            self.pruned = Some(now);
+        }
+        self.seen.insert(key.clone(), now);
+    }
+
+    pub fn log_access(&mut self, event: &AccessEvent<'_>) {
+        let now = SystemTime::now();
+        let line = match self.format {
+            LogFormat::Text => {
+                let tag = match event.decision {
+                    Decision::Deny => "DENIED",
+                    Decision::Allow => "ALLOWED",
+                    Decision::WouldDeny => "WOULD_DENY",
+                    Decision::Substitute => "SUBSTITUTED",
+                };
+                let op = operation_name(event.op);
+                let reason = event
+                    .reason
+                    .map(|reason| format!(" reason={reason}"))
+                    .unwrap_or_default();
+                format!(
+                    "[{tag}] {} {} op={op}{reason} path={}",
+                    humanize_timestamp(now),
+                    process_text(event.process),
+                    event.path
+                )
+            }
+            LogFormat::Json => {
+                let mut value = process_json(now, "access", event.process);
+                value["decision"] = decision_name(event.decision).into();
+                value["operation"] = operation_name(event.op).into();
+                value["path"] = event.path.into();
+                value["rule"] = json!(event.rule.map(|rule| &rule.entry));
+                value["rule_source"] = json!(event.rule.and_then(|rule| rule.source.as_ref()));
+                value["reason"] = json!(event.reason);
+                value["errno"] = json!(event.errno);
+                value.to_string()
+            }
+        };
+
+        let mut fields = process_fields("access", event.process);
+        fields.push((
+            "ACCESS_GUARD_DECISION",
+            decision_name(event.decision).into(),
+        ));
+        fields.push(("ACCESS_GUARD_OPERATION", operation_name(event.op).into()));
+        fields.push(("ACCESS_GUARD_PATH", event.path.into()));
+        if let Some(rule) = event.rule {
+            fields.push(("ACCESS_GUARD_RULE", rule.entry.clone()));
+            if let Some(ref source) = rule.source {
+                fields.push(("ACCESS_GUARD_RULE_SOURCE", source.display().to_string()));
+            }
+        }
+        if let Some(reason) = event.reason {
+            fields.push(("ACCESS_GUARD_REASON", reason.to_string()));
+        }
+        if let Some(errno) = event.errno {
+            fields.push(("ERRNO", errno.to_string()));
+        }
+        let severity = match event.decision {
+            Decision::Substitute => Severity::Alert,
+            Decision::Deny | Decision::WouldDeny => Severity::Warning,
+            Decision::Allow => Severity::Info,
+        };
+
+        let key = EventKey {
+            pid: event.process.pid,
+            path: event.path.to_string(),
+            op: event.op,
+            decision: event.decision,
+        };
+        self.remember(&key, Instant::now());
+        self.send(Entry {
+            key: Some(key),
+            record: Some(Record {
+                line,
+                severity,
+                fields,
+            }),
+        });
+    }
+
+    pub fn log_close(&mut self, event: &CloseEvent<'_>) {
+        let now = SystemTime::now();
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "[CLOSED] {} {} read={} written={} path={}",
+                humanize_timestamp(now),
+                process_text(event.process),
+                event.bytes_read,
+                event.bytes_written,
+                event.path
+            ),
+            LogFormat::Json => {
+                let mut value = process_json(now, "close", event.process);
+                value["path"] = event.path.into();
+                value["bytes_read"] = event.bytes_read.into();
+                value["bytes_written"] = event.bytes_written.into();
+                value.to_string()
+            }
+        };
+
+        let mut fields = process_fields("close", event.process);
+        fields.push(("ACCESS_GUARD_PATH", event.path.into()));
+        fields.push(("ACCESS_GUARD_BYTES_READ", event.bytes_read.to_string()));
+        fields.push((
+            "ACCESS_GUARD_BYTES_WRITTEN",
+            event.bytes_written.to_string(),
+        ));
+        self.send(Entry {
+            key: None,
+            record: Some(Record {
+                line,
+                severity: Severity::Info,
+                fields,
+            }),
+        });
+    }
+
+    pub fn log_reload(&mut self, event: &ReloadEvent<'_>) {
+        let now = SystemTime::now();
+        let mounted: Vec<String> = event
+            .mounted
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        let line = match self.format {
+            LogFormat::Text => match event.error {
+                Some(error) => format!(
+                    "[RELOAD_FAILED] {} source={} error={error:?}",
+                    humanize_timestamp(now),
+                    event.source.display()
+                ),
+                None if event.refused.is_empty() => format!(
+                    "[RELOADED] {} source={} added={} removed={} mounted={}",
+                    humanize_timestamp(now),
+                    event.source.display(),
+                    list_text(event.added),
+                    list_text(event.removed),
+                    list_text(&mounted)
+                ),
+                None => format!(
+                    "[RELOADED] {} source={} added={} removed={} mounted={} refused={}",
+                    humanize_timestamp(now),
+                    event.source.display(),
+                    list_text(event.added),
+                    list_text(event.removed),
+                    list_text(&mounted),
+                    list_text(event.refused)
+                ),
+            },
+            LogFormat::Json => {
+                let value = json!({
+                    "timestamp": humanize_timestamp_nanos(now),
+                    "event": "reload",
+                    "source": event.source,
+                    "added": event.added,
+                    "removed": event.removed,
+                    "refused": event.refused,
+                    "mounted": mounted,
+                    "error": event.error,
+                });
+                value.to_string()
+            }
         };
-        let line = format!(
-            "[DENIED] {timestamp} pid={pid} proc={process_name} op={op_str} path={path}\n"
-        );
 
-        if !self.quiet {
-            eprint!("{line}");
+        let mut fields = vec![
+            ("ACCESS_GUARD_EVENT", "reload".to_string()),
+            ("ACCESS_GUARD_SOURCE", event.source.display().to_string()),
+            ("ACCESS_GUARD_ADDED", event.added.join(",")),
+            ("ACCESS_GUARD_REMOVED", event.removed.join(",")),
+            ("ACCESS_GUARD_MOUNTED", mounted.join(",")),
+        ];
+        if !event.refused.is_empty() {
+            fields.push(("ACCESS_GUARD_REFUSED", event.refused.join(",")));
         }
+        if let Some(error) = event.error {
+            fields.push(("ACCESS_GUARD_ERROR", error.to_string()));
+        }
+        // Failures and attempts to loosen rules deserve attention
+        let loosened = !event.removed.is_empty() || !event.refused.is_empty();
+        let severity = if event.error.is_some() || loosened {
+            Severity::Warning
+        } else {
+            Severity::Info
+        };
+        self.send(Entry {
+            key: None,
+            record: Some(Record {
+                line,
+                severity,
+                fields,
+            }),
+        });
+    }
 
-        if let Some(ref mut f) = self.log_file {
-            let _ = f.write_all(line.as_bytes());
+    fn send(&mut self, entry: Entry) {
+        if let Some((ref sender, _)) = self.background {
+            if entry.key.is_none() {
+                // Close and reload records wait for room rather than get lost
+                let _ = sender.send(entry);
+            } else if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(entry) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        } else if let Some(ref mut writer) = self.writer {
+            writer.submit(entry, Instant::now());
         }
     }
 }
 
+impl Drop for Logger {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Applies deduplication and rate limiting, then writes lines out
+struct LogWriter {
+    sinks: Vec<Box<dyn LogSink>>,
+    format: LogFormat,
+    limits: LogLimits,
+    /// Open dedup windows: key -> (window start, repeats seen)
+    recent: HashMap<EventKey, (Instant, u64)>,
+    /// Start of the current rate-limit window and lines written in it
+    rate_start: Option<Instant>,
+    rate_count: u32,
+    suppressed: u64,
+    /// Access events the logger dropped before they reached the writer
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogWriter {
+    fn run(mut self, receiver: mpsc::Receiver<Entry>) -> Self {
+        loop {
+            match receiver.recv_timeout(WRITER_TICK) {
+                Ok(entry) => self.submit(entry, Instant::now()),
+                Err(mpsc::RecvTimeoutError::Timeout) => self.expire(Instant::now()),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        self
+    }
+
+    fn submit(&mut self, entry: Entry, now: Instant) {
+        self.expire(now);
+        let limited = entry.key.is_some();
+        let dedup_key = entry.key.filter(|_| !self.limits.dedup_window.is_zero());
+        if let Some(ref key) = dedup_key {
+            if let Some((_, repeats)) = self.recent.get_mut(key) {
+                *repeats += 1;
+                return;
+            }
+        }
+        let Some(record) = entry.record else {
+            // A repeat arriving after its window closed here
+            if let Some(key) = dedup_key {
+                self.recent.insert(key, (now, 1));
+            }
+            return;
+        };
+        if limited && self.limits.rate_limit > 0 {
+            if self.rate_count >= self.limits.rate_limit {
+                self.suppressed += 1;
+                return;
+            }
+            self.rate_count += 1;
+        }
+        if let Some(key) = dedup_key {
+            self.recent.insert(key, (now, 0));
+        }
+        self.write(&record);
+    }
+
+    /// Close dedup and rate windows that ended before `now`
+    fn expire(&mut self, now: Instant) {
+        let window = self.limits.dedup_window;
+        let expired: Vec<EventKey> = self
+            .recent
+            .iter()
+            .filter(|(_, (start, _))| now.duration_since(*start) >= window)
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.close_windows(expired);
+
+        let rate_start = *self.rate_start.get_or_insert(now);
+        if now.duration_since(rate_start) >= RATE_WINDOW {
+            self.write_suppressed();
+            self.rate_start = Some(now);
+            self.rate_count = 0;
+        }
+    }
+
+    /// Close all open windows
+    fn flush(&mut self) {
+        let keys = self.recent.keys().cloned().collect();
+        self.close_windows(keys);
+        self.write_suppressed();
+    }
+
+    /// Drop dedup windows, reporting those that absorbed repeats in the
+    /// order they were opened
+    fn close_windows(&mut self, keys: Vec<EventKey>) {
+        let mut closed: Vec<(Instant, EventKey, u64)> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let (start, repeats) = self.recent.remove(&key)?;
+                Some((start, key, repeats))
+            })
+            .collect();
+        closed.sort_by_key(|(start, _, _)| *start);
+        for (_, key, repeats) in closed {
+            if repeats > 0 {
+                self.write_repeated(&key, repeats);
+            }
+        }
+    }
+
+    fn write_repeated(&mut self, key: &EventKey, count: u64) {
+        let now = SystemTime::now();
+        let decision = decision_name(key.decision);
+        let op = operation_name(key.op);
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "[REPEATED] {} count={count} decision={decision} pid={} op={op} path={}",
+                humanize_timestamp(now),
+                key.pid,
+                key.path
+            ),
+            LogFormat::Json => {
+                let value = json!({
+                    "timestamp": humanize_timestamp_nanos(now),
+                    "event": "repeat",
+                    "count": count,
+                    "decision": decision,
+                    "pid": key.pid,
+                    "operation": op,
+                    "path": key.path,
+                });
+                value.to_string()
+            }
+        };
+        let fields = vec![
+            ("ACCESS_GUARD_EVENT", "repeat".to_string()),
+            ("ACCESS_GUARD_COUNT", count.to_string()),
+            ("ACCESS_GUARD_DECISION", decision.to_string()),
+            ("OBJECT_PID", key.pid.to_string()),
+            ("ACCESS_GUARD_OPERATION", op.to_string()),
+            ("ACCESS_GUARD_PATH", key.path.clone()),
+        ];
+        self.write(&Record {
+            line,
+            severity: Severity::Info,
+            fields,
+        });
+    }
+
+    fn write_suppressed(&mut self) {
+        self.suppressed += self.dropped.swap(0, Ordering::Relaxed);
+        if self.suppressed == 0 {
+            return;
+        }
+        let now = SystemTime::now();
+        let count = std::mem::take(&mut self.suppressed);
+        let line = match self.format {
+            LogFormat::Text => format!("[SUPPRESSED] {} count={count}", humanize_timestamp(now)),
+            LogFormat::Json => {
+                let value = json!({
+                    "timestamp": humanize_timestamp_nanos(now),
+                    "event": "suppressed",
+                    "count": count,
+                });
+                value.to_string()
+            }
+        };
+        let fields = vec![
+            ("ACCESS_GUARD_EVENT", "suppressed".to_string()),
+            ("ACCESS_GUARD_COUNT", count.to_string()),
+        ];
+        self.write(&Record {
+            line,
+            severity: Severity::Info,
+            fields,
+        });
+    }
+
+    fn write(&mut self, record: &Record) {
+        for sink in &mut self.sinks {
+            sink.write(record);
+        }
+    }
+}
+
+pub fn operation_name(op: Operation) -> &'static str {
+    match op {
+        Operation::Read => "read",
+        Operation::Write => "write",
+        Operation::Execute => "execute",
+        Operation::Stat => "stat",
+        Operation::List => "list",
+        Operation::Create => "create",
+        Operation::Delete => "delete",
+        Operation::Rename => "rename",
+        Operation::Overwrite => "overwrite",
+    }
+}
+
+fn decision_name(decision: Decision) -> &'static str {
+    match decision {
+        Decision::Deny => "deny",
+        Decision::Allow => "allow",
+        Decision::WouldDeny => "would_deny",
+        Decision::Substitute => "substitute",
+    }
+}
+
+/// Comma separated items, or `-` if there are none
+fn list_text(items: &[String]) -> String {
+    if items.is_empty() {
+        "-".to_string()
+    } else {
+        items.join(",")
+    }
+}
+
+/// `pid=... proc=... exe=... cmdline="..." parents=...`
+fn process_text(process: &ProcessInfo) -> String {
+    let pid = process.pid;
+    let name = process.comm.as_deref().unwrap_or("?");
+    let exe = process
+        .exe
+        .as_ref()
+        .map_or("?".into(), |exe| exe.to_string_lossy());
+    let cmdline = process.cmdline.join(" ");
+    let parents = if process.ancestors.is_empty() {
+        "-".to_string()
+    } else {
+        let chain: Vec<String> = process
+            .ancestors
+            .iter()
+            .map(|(pid, comm)| format!("{comm}:{pid}"))
+            .collect();
+        chain.join(",")
+    };
+    format!("pid={pid} proc={name} exe={exe} cmdline={cmdline:?} parents={parents}")
+}
+
+/// JSON object with the fields common to all events
+fn process_json(now: SystemTime, event: &str, process: &ProcessInfo) -> serde_json::Value {
+    let parents: Vec<_> = process
+        .ancestors
+        .iter()
+        .map(|(pid, comm)| json!({ "pid": pid, "comm": comm }))
+        .collect();
+    json!({
+        "timestamp": humanize_timestamp_nanos(now),
+        "event": event,
+        "pid": process.pid,
+        "uid": process.uid,
+        "comm": process.comm,
+        "exe": process.exe,
+        "cmdline": process.cmdline,
+        "parents": parents,
+    })
+}
+
+/// Journal fields common to all events
+fn process_fields(event: &str, process: &ProcessInfo) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("ACCESS_GUARD_EVENT", event.to_string()),
+        ("OBJECT_PID", process.pid.to_string()),
+        ("ACCESS_GUARD_UID", process.uid.to_string()),
+        ("ACCESS_GUARD_CMDLINE", process.cmdline.join(" ")),
+    ];
+    if let Some(ref comm) = process.comm {
+        fields.push(("ACCESS_GUARD_COMM", comm.clone()));
+    }
+    if let Some(ref exe) = process.exe {
+        fields.push(("ACCESS_GUARD_EXE", exe.display().to_string()));
+    }
+    fields
+}
+
+/// UTC timestamp with nanoseconds: YYYY-MM-DDTHH:MM:SS.NNNNNNNNNZ
+fn humanize_timestamp_nanos(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let secs = humanize_timestamp(time);
+    format!("{}.{nanos:09}Z", secs.trim_end_matches('Z'))
+}
+
 fn humanize_timestamp(time: SystemTime) -> String {
     let dur = time
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -70,51 +1112,757 @@ fn days_to_date(days: u64) -> (u64, u64, u64) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read as IoRead;
+    use std::sync::{Arc, Mutex};
+
+    fn process(pid: u32, comm: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            comm: Some(comm.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn denial<'a>(process: &'a ProcessInfo, path: &'a str, op: Operation) -> AccessEvent<'a> {
+        AccessEvent {
+            process,
+            path,
+            op,
+            decision: Decision::Deny,
+            rule: None,
+            reason: None,
+            errno: Some(libc::EACCES),
+        }
+    }
+
+    /// Sink collecting lines in memory
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<String>>);
+
+    impl LogSink for Capture {
+        fn write(&mut self, record: &Record) {
+            let mut out = self.0.lock().unwrap();
+            out.push_str(&record.line);
+            out.push('\n');
+        }
+    }
+
+    impl Capture {
+        fn contents(&self) -> String {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    fn plain(line: &str) -> Record {
+        Record {
+            line: line.to_string(),
+            severity: Severity::Info,
+            fields: Vec::new(),
+        }
+    }
+
+    fn captured() -> (Logger, Capture) {
+        let capture = Capture::default();
+        (Logger::new(vec![Box::new(capture.clone())]), capture)
+    }
 
     #[test]
     fn test_log_format() {
-        let mut buf = Vec::new();
-        {
-            let file = tempfile::tempfile().unwrap();
-            let mut logger = Logger::new(true, Some(file));
-            logger.log_denied(1234, "cat", "/home/user/.env", Operation::Read);
-            // Read back from the file
-            let file = logger.log_file.as_mut().unwrap();
-            file.flush().unwrap();
-            use std::io::Seek;
-            file.seek(std::io::SeekFrom::Start(0)).unwrap();
-            file.read_to_end(&mut buf).unwrap();
-        }
-        let output = String::from_utf8(buf).unwrap();
+        let (mut logger, capture) = captured();
+        let process = ProcessInfo {
+            pid: 1234,
+            uid: 1000,
+            comm: Some("cat".to_string()),
+            exe: Some("/usr/bin/cat".into()),
+            cmdline: vec!["cat".to_string(), ".env".to_string()],
+            ancestors: vec![(1200, "sh".to_string()), (1100, "claude".to_string())],
+        };
+        logger.log_access(&denial(&process, "/home/user/.env", Operation::Read));
+        let output = capture.contents();
         assert!(output.starts_with("[DENIED] "));
         assert!(output.contains("pid=1234"));
         assert!(output.contains("proc=cat"));
+        assert!(output.contains("exe=/usr/bin/cat"));
+        assert!(output.contains(r#"cmdline="cat .env""#));
+        assert!(output.contains("parents=sh:1200,claude:1100"));
         assert!(output.contains("op=read"));
         assert!(output.contains("path=/home/user/.env"));
     }
 
     #[test]
     fn test_quiet_suppresses_stderr() {
-        // quiet=true should not panic or error
-        let mut logger = Logger::new(true, None);
-        logger.log_denied(1, "test", "/tmp/file", Operation::Write);
+        let file = Path::new("/tmp/guard.log");
+        assert_eq!(select_sinks(&[], &[], None, false), vec![SinkSpec::Stderr]);
+        assert!(select_sinks(&[], &[], None, true).is_empty());
+        assert_eq!(
+            select_sinks(&[], &[], Some(file), true),
+            vec![SinkSpec::File(file.into())]
+        );
+
+        // No sinks at all should not panic or error
+        let mut logger = Logger::new(Vec::new());
+        logger.log_access(&denial(&process(1, "test"), "/tmp/file", Operation::Write));
+    }
+
+    #[test]
+    fn test_select_sinks_precedence() {
+        let configured = [SinkSpec::Journald, SinkSpec::Stderr];
+        assert_eq!(select_sinks(&[], &configured, None, false), configured);
+        assert_eq!(
+            select_sinks(&[], &configured, None, true),
+            vec![SinkSpec::Journald]
+        );
+        assert_eq!(
+            select_sinks(&[SinkSpec::Syslog], &configured, None, false),
+            vec![SinkSpec::Syslog]
+        );
+    }
+
+    #[test]
+    fn test_parse_sink_spec() {
+        assert_eq!("stderr".parse(), Ok(SinkSpec::Stderr));
+        assert_eq!("syslog".parse(), Ok(SinkSpec::Syslog));
+        assert_eq!("journald".parse(), Ok(SinkSpec::Journald));
+        assert_eq!(
+            "file:logs/a b.log".parse(),
+            Ok(SinkSpec::File("logs/a b.log".into()))
+        );
+        assert!("file:".parse::<SinkSpec>().is_err());
+        assert!("kafka".parse::<SinkSpec>().is_err());
     }
 
     #[test]
     fn test_file_output() {
-        let file = tempfile::tempfile().unwrap();
-        let mut logger = Logger::new(true, Some(file));
-        logger.log_denied(42, "bash", "/etc/shadow", Operation::Read);
-
-        let file = logger.log_file.as_mut().unwrap();
-        file.flush().unwrap();
-        use std::io::Seek;
-        file.seek(std::io::SeekFrom::Start(0)).unwrap();
-        let mut content = String::new();
-        file.read_to_string(&mut content).unwrap();
-        assert!(content.contains("[DENIED]"));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied.log");
+        std::fs::write(&path, "previous session\n").unwrap();
+        let sinks = open_sinks(&[SinkSpec::File(path.clone())], FileOptions::default()).unwrap();
+        let mut logger = Logger::new(sinks);
+        logger.log_access(&denial(
+            &process(42, "bash"),
+            "/etc/shadow",
+            Operation::Read,
+        ));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("previous session\n[DENIED]"));
         assert!(content.contains("pid=42"));
+        assert!(content.ends_with("path=/etc/shadow\n"));
+    }
+
+    #[test]
+    fn test_file_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied.log");
+        let options = FileOptions {
+            max_size: Some(25),
+            ..Default::default()
+        };
+        let mut sink = FileSink::open(&path, options).unwrap();
+        for line in ["first line", "second line", "third line"] {
+            sink.write(&plain(line));
+        }
+
+        let rotated = dir.path().join("denied.log.1");
+        assert_eq!(
+            std::fs::read_to_string(rotated).unwrap(),
+            "first line\nsecond line\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third line\n");
+    }
+
+    #[test]
+    fn test_file_rotation_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied.log");
+        let options = FileOptions {
+            max_size: Some(8),
+            max_files: 2,
+            ..Default::default()
+        };
+        let mut sink = FileSink::open(&path, options).unwrap();
+        for line in ["line 1", "line 2", "line 3", "line 4"] {
+            sink.write(&plain(line));
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("denied.log"), "line 4\n");
+        assert_eq!(read("denied.log.1"), "line 3\n");
+        assert_eq!(read("denied.log.2"), "line 2\n");
+        assert!(!dir.path().join("denied.log.3").exists());
+    }
+
+    #[test]
+    fn test_file_truncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied.log");
+        std::fs::write(&path, "previous session\n").unwrap();
+        let options = FileOptions {
+            truncate: true,
+            ..Default::default()
+        };
+        let mut sink = FileSink::open(&path, options).unwrap();
+        sink.write(&plain("new session"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new session\n");
+    }
+
+    #[test]
+    fn test_concurrent_sessions_share_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied.log");
+        let options = FileOptions {
+            max_size: Some(4096),
+            max_files: 100,
+            ..Default::default()
+        };
+        let writers: Vec<_> = (0..4)
+            .map(|session| {
+                let mut sink = FileSink::open(&path, options).unwrap();
+                std::thread::spawn(move || {
+                    for i in 0..250 {
+                        let line = format!("session={session} event={i:03} {}", "x".repeat(40));
+                        sink.write(&plain(&line));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut lines = Vec::new();
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let content = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(content.len() <= 4096);
+            lines.extend(content.lines().map(String::from));
+        }
+        assert_eq!(lines.len(), 1000);
+        assert!(lines
+            .iter()
+            .all(|line| line.len() == 60 && line.ends_with('x')));
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 1000);
+    }
+
+    #[test]
+    fn test_syslog_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let mut sink = SyslogSink::connect(&path).unwrap();
+        sink.write(&Record {
+            line: "[DENIED] op=read path=/p/.env".to_string(),
+            severity: Severity::Warning,
+            fields: Vec::new(),
+        });
+
+        let mut buf = [0u8; 512];
+        let n = server.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..n]).unwrap();
+        let expected = format!(
+            "<84>fuse-access-guard[{}]: [DENIED] op=read path=/p/.env",
+            std::process::id()
+        );
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_journald_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let sinks: Vec<Box<dyn LogSink>> = vec![Box::new(JournaldSink::connect(&path).unwrap())];
+        let mut logger = Logger::new(sinks);
+        let rule = DenyRule {
+            operation: Operation::Read,
+            pattern: crate::rules::PathPattern::Exact("/p/.env".into()),
+            hide: false,
+            entry: "Read(./.env)".to_string(),
+            source: None,
+            implicit: false,
+            audit_only: false,
+            redact: false,
+            substitute: None,
+        };
+        let cat = process(7, "cat");
+        let mut event = denial(&cat, "/p/.env", Operation::Read);
+        event.rule = Some(&rule);
+        logger.log_access(&event);
+
+        let mut buf = [0u8; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let payload = std::str::from_utf8(&buf[..n]).unwrap();
+        let fields: Vec<&str> = payload.lines().collect();
+        assert!(fields[0].starts_with("MESSAGE=[DENIED] "));
+        assert_eq!(
+            fields[1..3],
+            ["PRIORITY=4", "SYSLOG_IDENTIFIER=fuse-access-guard"]
+        );
+        for field in [
+            "ACCESS_GUARD_EVENT=access",
+            "OBJECT_PID=7",
+            "ACCESS_GUARD_COMM=cat",
+            "ACCESS_GUARD_DECISION=deny",
+            "ACCESS_GUARD_OPERATION=read",
+            "ACCESS_GUARD_PATH=/p/.env",
+            "ACCESS_GUARD_RULE=Read(./.env)",
+            "ERRNO=13",
+        ] {
+            assert!(fields.contains(&field), "missing {field} in {payload}");
+        }
+    }
+
+    #[test]
+    fn test_journal_payload_multiline_value() {
+        let record = Record {
+            line: "a".to_string(),
+            severity: Severity::Info,
+            fields: vec![("ACCESS_GUARD_PATH", "/p/x\ny".to_string())],
+        };
+        let payload = journal_payload(&record);
+        let mut expected = b"MESSAGE=a\nPRIORITY=6\nSYSLOG_IDENTIFIER=fuse-access-guard\n".to_vec();
+        expected.extend_from_slice(b"ACCESS_GUARD_PATH\n");
+        expected.extend_from_slice(&6u64.to_le_bytes());
+        expected.extend_from_slice(b"/p/x\ny\n");
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn test_missing_socket_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("no-such-socket");
+        let err = JournaldSink::connect(&path).err().unwrap();
+        assert!(err.to_string().contains("no-such-socket"));
+    }
+
+    #[test]
+    fn test_stat_logged_distinctly() {
+        let (mut logger, capture) = captured();
+        logger.log_access(&denial(
+            &process(7, "ls"),
+            "/home/user/id_rsa",
+            Operation::Stat,
+        ));
+
+        let content = capture.contents();
+        assert!(content.contains("op=stat"));
+    }
+
+    #[test]
+    fn test_unresolved_process() {
+        let (mut logger, capture) = captured();
+        let gone = ProcessInfo {
+            pid: 9,
+            ..Default::default()
+        };
+        logger.log_access(&denial(&gone, "/p/.env", Operation::Read));
+
+        let content = capture.contents();
+        assert!(content.contains(r#"proc=? exe=? cmdline="" parents=- op=read"#));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let (logger, capture) = captured();
+        let mut logger = logger.with_format(LogFormat::Json);
+        let process = ProcessInfo {
+            pid: 1234,
+            uid: 1000,
+            comm: Some("cat".to_string()),
+            exe: Some("/usr/bin/cat".into()),
+            cmdline: vec!["cat".to_string(), "my file=1".to_string()],
+            ancestors: vec![(1200, "sh".to_string())],
+        };
+        let rule = DenyRule {
+            operation: Operation::Read,
+            pattern: crate::rules::PathPattern::Exact("/p/my file=1".into()),
+            hide: false,
+            entry: "Read(./my file=1)".to_string(),
+            source: Some("/p/.claude/settings.json".into()),
+            implicit: false,
+            audit_only: false,
+            redact: false,
+            substitute: None,
+        };
+        let mut event = denial(&process, "/p/my file=1", Operation::Read);
+        event.rule = Some(&rule);
+        logger.log_access(&event);
+        logger.log_access(&denial(&process, "/p/other", Operation::Stat));
+
+        let content = capture.contents();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        let first = &lines[0];
+        assert_eq!(first["event"], "access");
+        assert_eq!(first["decision"], "deny");
+        assert_eq!(first["pid"], 1234);
+        assert_eq!(first["uid"], 1000);
+        assert_eq!(first["exe"], "/usr/bin/cat");
+        assert_eq!(first["cmdline"][1], "my file=1");
+        assert_eq!(first["parents"][0]["comm"], "sh");
+        assert_eq!(first["operation"], "read");
+        assert_eq!(first["path"], "/p/my file=1");
+        assert_eq!(first["rule"], "Read(./my file=1)");
+        assert_eq!(first["rule_source"], "/p/.claude/settings.json");
+        assert_eq!(first["errno"], libc::EACCES);
+        assert!(lines[1]["rule"].is_null());
+        assert_eq!(lines[1]["operation"], "stat");
+    }
+
+    #[test]
+    fn test_audit_events() {
+        let (mut logger, capture) = captured();
+        let cat = process(5, "cat");
+        let mut event = denial(&cat, "/p/id.pem", Operation::Read);
+        event.decision = Decision::Allow;
+        event.errno = None;
+        logger.log_access(&event);
+        logger.log_close(&CloseEvent {
+            process: &cat,
+            path: "/p/id.pem",
+            bytes_read: 1704,
+            bytes_written: 0,
+        });
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].starts_with("[ALLOWED] "));
+        assert!(lines[0].ends_with("op=read path=/p/id.pem"));
+        assert!(lines[1].starts_with("[CLOSED] "));
+        assert!(lines[1].ends_with("read=1704 written=0 path=/p/id.pem"));
+    }
+
+    #[test]
+    fn test_would_deny_events() {
+        let (mut logger, capture) = captured();
+        let rm = process(8, "rm");
+        let mut event = denial(&rm, "/p/migrations/001.sql", Operation::Delete);
+        event.decision = Decision::WouldDeny;
+        event.errno = None;
+        logger.log_access(&event);
+        let mut logger = logger.with_format(LogFormat::Json);
+        logger.log_access(&event);
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].starts_with("[WOULD_DENY] "));
+        assert!(lines[0].ends_with("op=delete path=/p/migrations/001.sql"));
+        let value: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(value["decision"], "would_deny");
+        assert!(value["errno"].is_null());
+    }
+
+    #[test]
+    fn test_substitute_events() {
+        let (mut logger, capture) = captured();
+        let cat = process(10, "cat");
+        let mut event = denial(&cat, "/p/.env", Operation::Read);
+        event.decision = Decision::Substitute;
+        event.reason = Some("substitute:decoy");
+        event.errno = None;
+        logger.log_access(&event);
+        let mut logger = logger.with_format(LogFormat::Json);
+        logger.log_access(&event);
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].starts_with("[SUBSTITUTED] "));
+        assert!(lines[0].ends_with("op=read reason=substitute:decoy path=/p/.env"));
+        let value: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(value["decision"], "substitute");
+        assert!(value["errno"].is_null());
+        assert_eq!(
+            syslog_message(&Record {
+                line: "x".to_string(),
+                severity: Severity::Alert,
+                fields: Vec::new(),
+            }),
+            format!("<81>{IDENTIFIER}[{}]: x", std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_denial_reason() {
+        let (mut logger, capture) = captured();
+        let cat = process(9, "cat");
+        let mut event = denial(&cat, "/p/notes.txt", Operation::Read);
+        event.reason = Some("secret:private-key");
+        logger.log_access(&event);
+        let mut logger = logger.with_format(LogFormat::Json);
+        logger.log_access(&denial(&cat, "/p/other.txt", Operation::Read));
+        logger.log_access(&event);
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].ends_with("op=read reason=secret:private-key path=/p/notes.txt"));
+        let value: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert!(value["reason"].is_null());
+        let value: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+        assert_eq!(value["reason"], "secret:private-key");
+    }
+
+    #[test]
+    fn test_json_close_event() {
+        let (logger, capture) = captured();
+        let mut logger = logger.with_format(LogFormat::Json);
+        logger.log_close(&CloseEvent {
+            process: &process(5, "tee"),
+            path: "/p/out.log",
+            bytes_read: 0,
+            bytes_written: 12,
+        });
+
+        let value: serde_json::Value = serde_json::from_str(&capture.contents()).unwrap();
+        assert_eq!(value["event"], "close");
+        assert_eq!(value["comm"], "tee");
+        assert_eq!(value["bytes_written"], 12);
+        assert!(value.get("decision").is_none());
+    }
+
+    fn limited(dedup_ms: u64, rate_limit: u32) -> (Logger, Capture) {
+        let limits = LogLimits {
+            dedup_window: Duration::from_millis(dedup_ms),
+            rate_limit,
+        };
+        let (logger, capture) = captured();
+        (logger.with_limits(limits), capture)
+    }
+
+    #[test]
+    fn test_dedup_identical_events() {
+        let (mut logger, capture) = limited(60_000, 0);
+        let rg = process(3, "rg");
+        for _ in 0..5 {
+            logger.log_access(&denial(&rg, "/p/.env", Operation::Read));
+        }
+        logger.log_access(&denial(&rg, "/p/.env", Operation::Stat));
+        logger.log_access(&denial(&process(4, "rg"), "/p/.env", Operation::Read));
+        logger.flush();
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4, "{content}");
+        assert!(lines[0].contains(" pid=3 ") && lines[0].ends_with("op=read path=/p/.env"));
+        assert!(lines[1].ends_with("op=stat path=/p/.env"));
+        assert!(lines[2].contains("pid=4 "));
+        assert!(lines[3].starts_with("[REPEATED] "));
+        assert!(lines[3].ends_with("count=4 decision=deny pid=3 op=read path=/p/.env"));
+    }
+
+    #[test]
+    fn test_dedup_window_expires() {
+        let (mut logger, capture) = limited(1000, 0);
+        let entry = || Entry {
+            key: Some(EventKey {
+                pid: 3,
+                path: "/p/.env".to_string(),
+                op: Operation::Read,
+                decision: Decision::Deny,
+            }),
+            record: Some(plain("event")),
+        };
+        let start = Instant::now();
+        let writer = logger.writer.as_mut().unwrap();
+        writer.submit(entry(), start);
+        writer.submit(entry(), start + Duration::from_millis(500));
+        writer.submit(entry(), start + Duration::from_millis(1500));
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3, "{content}");
+        assert_eq!(lines[0], "event");
+        assert!(lines[1].contains("[REPEATED] ") && lines[1].contains(" count=1 "));
+        assert_eq!(lines[2], "event");
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let (mut logger, capture) = limited(0, 2);
+        let start = Instant::now();
+        let writer = logger.writer.as_mut().unwrap();
+        let event = |i: u32| Entry {
+            key: Some(EventKey {
+                pid: i,
+                path: "/p/.env".to_string(),
+                op: Operation::Read,
+                decision: Decision::Deny,
+            }),
+            record: Some(plain(&format!("event {i}"))),
+        };
+        for i in 0..5 {
+            writer.submit(event(i), start);
+        }
+        // Close and reload records are never suppressed
+        let record = Some(plain("closed"));
+        writer.submit(Entry { key: None, record }, start);
+        writer.submit(event(5), start + Duration::from_secs(1));
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 5, "{content}");
+        assert_eq!(lines[..3], ["event 0", "event 1", "closed"]);
+        assert!(lines[3].starts_with("[SUPPRESSED] ") && lines[3].ends_with(" count=3"));
+        assert_eq!(lines[4], "event 5");
+    }
+
+    #[test]
+    fn test_coalesce_before_resolving() {
+        let (mut logger, capture) = limited(60_000, 0);
+        let cat = process(5, "cat");
+        let (path, op, decision) = ("/p/.env", Operation::Read, Decision::Deny);
+        assert!(!logger.coalesce(5, path, op, decision));
+        logger.log_access(&denial(&cat, path, op));
+        assert!(logger.coalesce(5, path, op, decision));
+        assert!(logger.coalesce(5, path, op, decision));
+        assert!(!logger.coalesce(6, path, op, decision));
+        assert!(!logger.coalesce(5, path, Operation::Stat, decision));
+        logger.flush();
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2, "{content}");
+        assert!(lines[0].starts_with("[DENIED] "));
+        assert!(lines[1].starts_with("[REPEATED] ") && lines[1].contains(" count=2 "));
+
+        // Without a dedup window nothing is coalesced
+        let (mut logger, _) = limited(0, 0);
+        logger.log_access(&denial(&cat, path, op));
+        assert!(!logger.coalesce(5, path, op, decision));
+    }
+
+    #[test]
+    fn test_full_queue_drops_access_events() {
+        let (mut logger, capture) = limited(0, 0);
+        let writer = logger.writer.take().unwrap();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let (start, started) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            started.recv().unwrap();
+            writer.run(receiver)
+        });
+        logger.background = Some((sender, handle));
+
+        let cat = process(5, "cat");
+        for path in ["/p/a", "/p/b", "/p/c"] {
+            logger.log_access(&denial(&cat, path, Operation::Read));
+        }
+        start.send(()).unwrap();
+        logger.flush();
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2, "{content}");
+        assert!(lines[0].starts_with("[DENIED] ") && lines[0].ends_with(" path=/p/a"));
+        assert!(lines[1].starts_with("[SUPPRESSED] ") && lines[1].ends_with(" count=2"));
+    }
+
+    #[test]
+    fn test_json_summary_events() {
+        let (logger, capture) = limited(60_000, 1);
+        let mut logger = logger.with_format(LogFormat::Json);
+        let cat = process(5, "cat");
+        logger.log_access(&denial(&cat, "/p/.env", Operation::Read));
+        logger.log_access(&denial(&cat, "/p/.env", Operation::Read));
+        logger.log_access(&denial(&cat, "/p/secret", Operation::Read));
+        logger.flush();
+
+        let content = capture.contents();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["event"], "repeat");
+        assert_eq!(lines[1]["count"], 1);
+        assert_eq!(lines[1]["decision"], "deny");
+        assert_eq!(lines[1]["operation"], "read");
+        assert_eq!(lines[1]["path"], "/p/.env");
+        assert_eq!(lines[2]["event"], "suppressed");
+        assert_eq!(lines[2]["count"], 1);
+    }
+
+    #[test]
+    fn test_reload_events() {
+        let (mut logger, capture) = captured();
+        let source = Path::new("/p/.claude/settings.json");
+        logger.log_reload(&ReloadEvent {
+            source,
+            added: &["Read(./id_rsa)".to_string(), "Write(./a)".to_string()],
+            removed: &[],
+            refused: &[],
+            mounted: &[PathBuf::from("/srv")],
+            error: None,
+        });
+        logger.log_reload(&ReloadEvent {
+            source,
+            added: &[],
+            removed: &[],
+            refused: &[],
+            mounted: &[],
+            error: Some("expected value at line 1 column 1"),
+        });
+        logger.log_reload(&ReloadEvent {
+            source,
+            added: &[],
+            removed: &[],
+            refused: &["Read(./.env)".to_string()],
+            mounted: &[],
+            error: None,
+        });
+        let output = capture.contents();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("[RELOADED] "));
+        assert!(lines[0].ends_with(
+            " source=/p/.claude/settings.json added=Read(./id_rsa),Write(./a) removed=- mounted=/srv"
+        ));
+        assert!(lines[1].starts_with("[RELOAD_FAILED] "));
+        assert!(lines[1].ends_with(r#"error="expected value at line 1 column 1""#));
+        assert!(lines[2].ends_with(" removed=- mounted=- refused=Read(./.env)"));
+
+        let (logger, capture) = captured();
+        let mut logger = logger.with_format(LogFormat::Json);
+        logger.log_reload(&ReloadEvent {
+            source,
+            added: &[],
+            removed: &["Read(./.env)".to_string()],
+            refused: &[],
+            mounted: &[],
+            error: None,
+        });
+        let value: serde_json::Value = serde_json::from_str(capture.contents().trim()).unwrap();
+        assert_eq!(value["event"], "reload");
+        assert_eq!(value["removed"], json!(["Read(./.env)"]));
+        assert_eq!(value["refused"], json!([]));
+        assert_eq!(value["error"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_background_writer() {
+        let (mut logger, capture) = limited(60_000, 0);
+        logger.spawn_writer();
+        assert!(logger.writer.is_none());
+        let cat = process(5, "cat");
+        logger.log_access(&denial(&cat, "/p/.env", Operation::Read));
+        logger.log_access(&denial(&cat, "/p/.env", Operation::Read));
+        logger.flush();
+
+        let content = capture.contents();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2, "{content}");
+        assert!(lines[0].starts_with("[DENIED] "));
+        assert!(lines[1].starts_with("[REPEATED] "));
+    }
+
+    #[test]
+    fn test_timestamp_nanos_format() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::new(1, 5);
+        assert_eq!(
+            humanize_timestamp_nanos(time),
+            "1970-01-01T00:00:01.000000005Z"
+        );
     }
 
     #[test]
@@ -1,12 +1,21 @@
 mod cli;
 mod config;
+mod control;
+mod fs_view;
+mod learn;
 mod logger;
 mod namespace;
 mod passthrough_fs;
+mod process;
+mod redact;
+mod reload;
+mod report;
 mod rules;
+mod scanner;
 
 use std::fs::File;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 fn main() {
     let code = match run() {
@@ -26,28 +35,144 @@ fn run() -> Result<i32, Box<dyn std::error::Error>> {
     let cwd = std::env::current_dir()?;
     let settings = config::load_settings(&cwd)?;
 
-    // Build access rules
-    let rules = rules::AccessRules::new(&settings, &cwd, args.exclude_exec.clone())?;
-    let rules = Arc::new(rules);
+    // Build access rules, the same way again whenever the settings change.
+    // Once the FUSE daemon mounts over files, it reaches them through `fs`.
+    let fs = fs_view::FsView::default();
+    let build_rules = {
+        let cwd = cwd.clone();
+        let fs = fs.clone();
+        let exclude_exec = args.exclude_exec.clone();
+        let hide = args.hide;
+        let scan_roots = args.protect_inodes.then(|| {
+            if args.inode_scan_root.is_empty() {
+                vec![cwd.clone()]
+            } else {
+                args.inode_scan_root.clone()
+            }
+        });
+        let audit = args.audit;
+        let protect_settings = !args.no_protect_settings;
+        let enforce = args.enforcing();
+        let learn = args.learning();
+        let scan_secrets = args.scan_secrets.then_some(args.scan_max_size);
+        let redact_max_size = args.redact_max_size;
+        move |settings: &config::Settings| {
+            let excluded = exclude_exec.clone();
+            let mut rules = rules::AccessRules::new(settings, &cwd, excluded, fs.clone())?;
+            if protect_settings {
+                rules.protect_settings();
+            }
+            if hide {
+                rules.hide_read_denied();
+            }
+            if let Some(ref scan_roots) = scan_roots {
+                rules.enable_inode_protection(scan_roots);
+            }
+            rules.enable_audit(audit, &cwd);
+            rules.set_enforce(enforce);
+            if learn {
+                rules.enable_learning(&cwd);
+            }
+            if let Some(max_size) = scan_secrets {
+                rules.enable_secret_scan(max_size, &cwd);
+            }
+            rules.set_redact_limit(redact_max_size);
+            Ok(rules)
+        }
+    };
+    let rules = build_rules(&settings)?;
+    let reload = match settings.source {
+        Some(ref path) if !args.no_reload => {
+            let watch = reload::SettingsWatch::new(path, Box::new(build_rules))?;
+            Some(watch.allow_removals(!args.no_protect_settings))
+        }
+        _ => None,
+    };
 
     // Set up logger
-    let log_file = match &args.log_file {
+    let configured = settings
+        .access_guard
+        .log
+        .sinks
+        .iter()
+        .map(|sink| sink.parse())
+        .collect::<Result<Vec<logger::SinkSpec>, _>>()?;
+    let specs = logger::select_sinks(
+        &args.log_sink,
+        &configured,
+        args.log_file.as_deref(),
+        args.quiet,
+    );
+    let log_settings = &settings.access_guard.log;
+    let file_options = logger::FileOptions {
+        truncate: args.log_truncate,
+        max_size: args.log_max_size.or(log_settings.max_file_size),
+        max_files: args.log_max_files.or(log_settings.max_files).unwrap_or(1) as usize,
+    };
+    let mut sinks = logger::open_sinks(&specs, file_options)?;
+    let control = args.control_socket_path().map(|path| control::ControlSocket {
+        path: cwd.join(path),
+        cwd: cwd.clone(),
+        subscribers: control::Subscribers::default(),
+    });
+    if let Some(ref socket) = control {
+        sinks.push(Box::new(socket.subscribers.clone()));
+    }
+    let limits = logger::LogLimits {
+        dedup_window: Duration::from_millis(args.log_dedup_window),
+        rate_limit: args.log_rate_limit,
+    };
+    let logger = logger::Logger::new(sinks)
+        .with_format(args.log_format)
+        .with_limits(limits);
+    let logger = Arc::new(Mutex::new(logger));
+
+    // Set up end-of-run report
+    let report_file = match &args.report {
         Some(path) => Some(File::create(path)?),
         None => None,
     };
-    let logger = logger::Logger::new(args.quiet, log_file);
-    let logger = Arc::new(Mutex::new(logger));
+    let mut report = report::Report::new(args.summary, report_file).with_format(args.report_format);
+    if args.learning() {
+        let output = match &args.learn_output {
+            Some(path) => Some(File::create(path)?),
+            None => None,
+        };
+        report = report.with_learner(learn::Learner::new(&cwd, output));
+    }
+    let report = Arc::new(Mutex::new(report));
 
     // Compute mount points from deny rules
     let mount_points = namespace::compute_mount_points(&rules);
+    if args.verbose {
+        if !args.enforcing() {
+            eprintln!("fuse-access-guard: dry run: matching accesses are logged as WOULD_DENY, not denied");
+        }
+        for rule in rules.rules().iter().filter(|rule| rule.implicit) {
+            eprintln!("fuse-access-guard: implicit rule: {}", rule.entry);
+        }
+        if mount_points.is_empty() {
+            eprintln!("fuse-access-guard: mount plan: no mount points needed");
+        }
+        for mp in &mount_points {
+            eprintln!("fuse-access-guard: mount plan: {}", mp.target.display());
+        }
+        if let Some(ref socket) = control {
+            eprintln!("fuse-access-guard: control socket: {}", socket.path.display());
+        }
+    }
     // Build namespace config
     let ns_config = namespace::NamespaceConfig {
         mount_points,
         command: args.command_name().to_string(),
         args: args.command_args().iter().map(|s| s.to_string()).collect(),
+        control,
+        reload,
+        fs,
     };
 
     // Run in namespace
-    let exit_code = namespace::run_in_namespace(ns_config, rules, logger)?;
+    let rules = Arc::new(rules::SharedRules::new(rules));
+    let exit_code = namespace::run_in_namespace(ns_config, rules, logger, report)?;
     Ok(exit_code)
 }
@@ -1,17 +1,30 @@
-use std::collections::HashSet;
 use std::ffi::CString;
 use std::io::Read;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use nix::mount::{mount, MsFlags};
+use fuser::BackgroundSession;
+
+use nix::fcntl::{openat, OFlag};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::sched::{unshare, CloneFlags};
+use nix::sys::stat::Mode;
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::{fork, ForkResult, Pid};
 
+use crate::control::{self, Control, ControlSocket};
+use crate::fs_view::FsView;
 use crate::logger::Logger;
 use crate::passthrough_fs::PassthroughFs;
-use crate::rules::AccessRules;
+use crate::process::{held_paths, mount_namespace, namespace_peers};
+use crate::reload::SettingsWatch;
+use crate::report::Report;
+use crate::rules::{AccessRules, SharedRules};
+
+/// How long to wait for FUSE sessions to wind down after the command exits
+const UNMOUNT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
 #[derive(Debug)]
 pub struct MountPoint {
@@ -23,6 +36,150 @@ pub struct NamespaceConfig {
     pub mount_points: Vec<MountPoint>,
     pub command: String,
     pub args: Vec<String>,
+    /// Control socket served by the FUSE daemon, if requested
+    pub control: Option<ControlSocket>,
+    /// Settings watcher started by the FUSE daemon, unless disabled
+    pub reload: Option<SettingsWatch>,
+    /// Shared with the rules, which reach files below the mounts through it
+    pub fs: FsView,
+}
+
+/// The FUSE mounts of the daemon. Rule changes while the command runs may
+/// add mounts for directories not covered yet.
+pub struct Mounts {
+    /// Pid of the guarded command
+    root_pid: u32,
+    rules: Arc<SharedRules>,
+    logger: Arc<Mutex<Logger>>,
+    report: Arc<Mutex<Report>>,
+    /// Learns the directory underneath each mount
+    fs: FsView,
+    state: Mutex<MountState>,
+}
+
+#[derive(Default)]
+struct MountState {
+    targets: Vec<PathBuf>,
+    sessions: Vec<BackgroundSession>,
+}
+
+impl Mounts {
+    pub fn new(
+        root_pid: u32,
+        rules: Arc<SharedRules>,
+        logger: Arc<Mutex<Logger>>,
+        report: Arc<Mutex<Report>>,
+        fs: FsView,
+    ) -> Self {
+        Mounts {
+            root_pid,
+            rules,
+            logger,
+            report,
+            fs,
+            state: Mutex::new(MountState::default()),
+        }
+    }
+
+    /// Directories currently mounted
+    pub fn targets(&self) -> Vec<PathBuf> {
+        self.lock_state().targets.clone()
+    }
+
+    /// Mount the directories `rules` need that no mount covers yet,
+    /// returning the new mount targets. Mounts inside a new one are retired.
+    pub fn extend(&self, rules: &AccessRules) -> std::io::Result<Vec<PathBuf>> {
+        let mut state = self.lock_state();
+        let mut mounted = Vec::new();
+        for mp in uncovered_mount_points(rules, &state.targets) {
+            let source = std::fs::File::open(&mp.source)?;
+            let underneath = source.try_clone()?;
+            self.attach(&mut state, &mp, source)?;
+            retire_covered(&mut state, &mp.target, &underneath);
+            mounted.push(mp.target);
+        }
+        Ok(mounted)
+    }
+
+    /// Take the sessions for unmounting
+    fn take_sessions(&self) -> Vec<BackgroundSession> {
+        std::mem::take(&mut self.lock_state().sessions)
+    }
+
+    /// Record `target` as mounted without mounting anything
+    #[cfg(test)]
+    pub fn assume_mounted(&self, target: PathBuf) {
+        self.lock_state().targets.push(target);
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, MountState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Mount a passthrough filesystem of `source_fd` (opened on `mp.source`
+    /// before any mount could cover it) at `mp.target`
+    fn attach(
+        &self,
+        state: &mut MountState,
+        mp: &MountPoint,
+        source_fd: std::fs::File,
+    ) -> std::io::Result<()> {
+        self.fs.add_source(&mp.target, source_fd.try_clone()?);
+        let fs = PassthroughFs::new(
+            mp.source.clone(),
+            source_fd,
+            self.root_pid,
+            Arc::clone(&self.rules),
+            Arc::clone(&self.logger),
+            Arc::clone(&self.report),
+        );
+        // No DefaultPermissions: the kernel must forward access(2)
+        // to the daemon so deny rules apply (and are logged) there.
+        // Real permission checks still happen on the openat/fstatat
+        // calls against the source directory.
+        let options = vec![fuser::MountOption::FSName("fuse-access-guard".to_string())];
+        let session = fuser::spawn_mount2(fs, &mp.target, &options)?;
+        state.targets.push(mp.target.clone());
+        state.sessions.push(session);
+        Ok(())
+    }
+}
+
+/// Detach the mounts inside `target` after it has been mounted over, so
+/// that accesses below it are checked once, by the new mount, which reaches
+/// the real directories through `source`, the directory underneath it.
+/// Files already open below a retired mount stay served by its session
+/// until the end. While a guarded process works below `target` the mounts
+/// are kept, as its relative paths still reach them directly.
+fn retire_covered(state: &mut MountState, target: &Path, source: &std::fs::File) {
+    let covered = |dir: &PathBuf| dir != target && dir.starts_with(target);
+    if !state.targets.iter().any(covered) || !bypassed_mounts(&[target.to_path_buf()]).is_empty()
+    {
+        return;
+    }
+    state.targets.retain(|dir| {
+        if !covered(dir) {
+            return true;
+        }
+        let rel = dir.strip_prefix(target).unwrap_or(dir);
+        // The old mount is only reachable from below the new one
+        let flags = OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+        let detached = openat(Some(source.as_raw_fd()), rel, flags, Mode::empty())
+            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+            .and_then(|fd| {
+                let path = format!("/proc/self/fd/{}", fd.as_raw_fd());
+                umount2(path.as_str(), MntFlags::MNT_DETACH)
+            });
+        if let Err(e) = detached {
+            eprintln!(
+                "fuse-access-guard: failed to unmount {} below the new mount {}: {e}",
+                dir.display(),
+                target.display()
+            );
+            return true;
+        }
+        false
+    });
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -31,18 +188,45 @@ pub enum NamespaceError {
     ForkError(#[from] nix::Error),
     #[error("failed to execute command: {0}")]
     ExecError(String),
+    #[error("failed to create control socket {}: {error}", path.display())]
+    ControlSocket { path: PathBuf, error: std::io::Error },
 }
 
 /// Compute mount points from deny rules.
-/// Groups deny-rule paths by their parent directory to minimize mounts.
+///
+/// Each deny path is mounted at its deepest literal ancestor: the parent
+/// directory for exact paths, or the directory before the first component
+/// containing glob metacharacters (`*`, `?`, `[`). Mount points nested inside
+/// another mount point are merged into the outer one. If that directory does
+/// not exist yet, its nearest existing ancestor is mounted instead so that
+/// files and directories created later under it are still checked. Hardlinks
+/// of denied files found with inode protection, and paths audited for allowed
+/// accesses, are mounted the same way.
 pub fn compute_mount_points(rules: &AccessRules) -> Vec<MountPoint> {
-    let dirs: HashSet<PathBuf> = rules
+    let mut dirs: Vec<PathBuf> = rules
         .denied_paths()
         .iter()
-        .filter_map(|p| p.parent().map(|d| d.to_path_buf()))
+        .chain(rules.hardlink_siblings())
+        .chain(&rules.audited_paths())
+        .chain(&rules.observed_paths())
+        .filter_map(|p| literal_ancestor(p))
+        .filter_map(|d| nearest_existing_dir(&d, rules.fs()))
         .collect();
 
-    dirs.into_iter()
+    // Sorting by components places every ancestor directly before its descendants
+    dirs.sort();
+    dirs.dedup();
+
+    let mut merged: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if merged.last().is_some_and(|outer| dir.starts_with(outer)) {
+            continue;
+        }
+        merged.push(dir);
+    }
+
+    merged
+        .into_iter()
         .map(|d| MountPoint {
             source: d.clone(),
             target: d,
@@ -50,33 +234,122 @@ pub fn compute_mount_points(rules: &AccessRules) -> Vec<MountPoint> {
         .collect()
 }
 
+/// Mount points `rules` need that are not inside one of `mounted`. They may
+/// contain some of `mounted`, as a rule for a parent directory is added.
+pub fn uncovered_mount_points(rules: &AccessRules, mounted: &[PathBuf]) -> Vec<MountPoint> {
+    compute_mount_points(rules)
+        .into_iter()
+        .filter(|mp| !mounted.iter().any(|dir| mp.target.starts_with(dir)))
+        .collect()
+}
+
+/// Those of `targets`, mounted while the command runs, below which a guarded
+/// process already has its working directory or an open file descriptor.
+/// Paths relative to those reach the directory underneath the mount, so rules
+/// there are not enforced for that process.
+pub fn bypassed_mounts(targets: &[PathBuf]) -> Vec<PathBuf> {
+    if targets.is_empty() {
+        return Vec::new();
+    }
+    let held: Vec<PathBuf> = namespace_peers().into_iter().flat_map(held_paths).collect();
+    targets
+        .iter()
+        .filter(|target| held.iter().any(|path| path.starts_with(target)))
+        .cloned()
+        .collect()
+}
+
+/// Returns the deepest ancestor directory of `path` that contains no glob
+/// metacharacters.
+fn literal_ancestor(path: &Path) -> Option<PathBuf> {
+    let mut literal = PathBuf::new();
+    for component in path.components() {
+        let s = component.as_os_str().to_string_lossy();
+        if s.contains(['*', '?', '[']) {
+            return Some(literal);
+        }
+        literal.push(component);
+    }
+    literal.parent().map(|d| d.to_path_buf())
+}
+
+/// Returns the canonical path of `dir` itself if it is an existing directory,
+/// otherwise of its nearest ancestor that is. Mount sources are canonical so
+/// that virtual paths compare equal to canonicalized rule paths.
+fn nearest_existing_dir(dir: &Path, fs: &FsView) -> Option<PathBuf> {
+    dir.ancestors()
+        .filter(|a| fs.metadata(a).is_ok_and(|meta| meta.is_dir()))
+        .find_map(|a| fs.canonicalize(a).ok())
+}
+
 /// Run a command inside a mount namespace with FUSE access guards.
 ///
 /// Process model (double fork):
-/// 1. fork() -> child A (FUSE daemon)
+/// 1. Bind the control socket, if any -> fork() -> child A (FUSE daemon)
 /// 2. Child A: unshare(CLONE_NEWUSER | CLONE_NEWNS) -> uid/gid maps -> mount private
 ///    -> fork() -> child B (command runner)
-///    -> mount FUSE (background sessions)
-///    -> signal child B via pipe -> waitpid(child B) -> cleanup
+///    -> mount FUSE (background sessions) -> serve control socket
+///    -> watch settings -> signal child B via pipe -> waitpid(child B) -> cleanup -> report
 /// 3. Child B: wait for pipe signal -> execvp(command)
-/// 4. Parent: waitpid(child A) -> propagate exit code
+/// 4. Parent: waitpid(child A) -> remove the control socket -> propagate exit code
 pub fn run_in_namespace(
     config: NamespaceConfig,
-    rules: Arc<AccessRules>,
+    rules: Arc<SharedRules>,
     logger: Arc<Mutex<Logger>>,
+    report: Arc<Mutex<Report>>,
 ) -> Result<i32, NamespaceError> {
     if config.mount_points.is_empty() {
-        return run_command_directly(&config.command, &config.args);
+        if config.control.is_some() {
+            eprintln!("fuse-access-guard: no mount points, control socket not started");
+        }
+        let code = run_command_directly(&config.command, &config.args)?;
+        finish_report(&report, code);
+        return Ok(code);
     }
 
+    // Bind the control socket while its directory is still the real one.
+    // Clients are accepted here and passed on to the FUSE daemon.
+    let control = match config.control {
+        Some(ref socket) => {
+            let listener = control::bind(&socket.path)
+                .map_err(|error| NamespaceError::ControlSocket {
+                    path: socket.path.clone(),
+                    error,
+                })?;
+            if !socket_reachable(&socket.path, &config.mount_points) {
+                eprintln!(
+                    "fuse-access-guard: control socket {} is below a mount point and cannot \
+                     be reached by the command",
+                    socket.path.display()
+                );
+            }
+            let (gate, daemon) = UnixDatagram::pair().map_err(|error| {
+                NamespaceError::ControlSocket {
+                    path: socket.path.clone(),
+                    error,
+                }
+            })?;
+            Some((listener, gate, daemon))
+        }
+        None => None,
+    };
+
     match unsafe { fork() }? {
         ForkResult::Child => {
-            fuse_daemon_process(&config, &rules, &logger);
+            let clients = control.map(|(_, _, daemon)| daemon);
+            fuse_daemon_process(config, clients, &rules, &logger, &report);
             std::process::exit(127);
         }
         ForkResult::Parent { child } => {
-            let status = waitpid(child, None).map_err(NamespaceError::ForkError)?;
-            match status {
+            if let Some((listener, gate, daemon)) = control {
+                drop(daemon);
+                control::admit(listener, gate, mount_namespace(std::process::id()));
+            }
+            let status = waitpid(child, None).map_err(NamespaceError::ForkError);
+            if let Some(ref socket) = config.control {
+                let _ = std::fs::remove_file(&socket.path);
+            }
+            match status? {
                 WaitStatus::Exited(_, code) => Ok(code),
                 WaitStatus::Signaled(_, sig, _) => Ok(128 + sig as i32),
                 _ => Ok(1),
@@ -87,9 +360,11 @@ pub fn run_in_namespace(
 
 /// Child A: sets up namespace, forks child B FIRST, mounts FUSE, signals child B.
 fn fuse_daemon_process(
-    config: &NamespaceConfig,
-    rules: &Arc<AccessRules>,
+    mut config: NamespaceConfig,
+    clients: Option<UnixDatagram>,
+    rules: &Arc<SharedRules>,
     logger: &Arc<Mutex<Logger>>,
+    report: &Arc<Mutex<Report>>,
 ) {
     // Save uid/gid before entering user namespace
     let uid = nix::unistd::getuid();
@@ -145,6 +420,11 @@ fn fuse_daemon_process(
                 let _ = std::env::set_current_dir("/");
                 let _ = std::env::set_current_dir(&cwd);
             }
+            if let Some(ref socket) = config.control {
+                if socket_reachable(&socket.path, &config.mount_points) {
+                    std::env::set_var(control::SOCKET_ENV, &socket.path);
+                }
+            }
             exec_command(&config.command, &config.args);
         }
         Ok(ForkResult::Parent { child: grandchild }) => {
@@ -168,45 +448,77 @@ fn fuse_daemon_process(
                 }
             }
 
-            // 7. Mount FUSE AFTER forking (so FUSE threads only exist in this process)
-            let mut sessions = Vec::new();
+            // 7. Mount FUSE AFTER forking (so FUSE threads only exist in this process).
+            //    Log I/O likewise moves to its own thread only now.
+            if let Ok(mut logger) = logger.lock() {
+                logger.spawn_writer();
+            }
+            let mounts = Arc::new(Mounts::new(
+                grandchild.as_raw() as u32,
+                Arc::clone(rules),
+                Arc::clone(logger),
+                Arc::clone(report),
+                config.fs.clone(),
+            ));
             for (mp, source_fd) in source_fds {
-                let fs = PassthroughFs::new(
-                    mp.source.clone(),
-                    source_fd,
-                    Arc::clone(rules),
-                    Arc::clone(logger),
-                );
-                let options = vec![
-                    fuser::MountOption::FSName("fuse-access-guard".to_string()),
-                    fuser::MountOption::DefaultPermissions,
-                ];
-                match fuser::spawn_mount2(fs, &mp.target, &options) {
-                    Ok(session) => sessions.push(session),
-                    Err(e) => {
-                        eprintln!(
-                            "fuse-access-guard: FUSE mount failed on {}: {e}\n\
-                             Hint: ensure fuse3 is installed (apt install fuse3 libfuse3-dev)",
-                            mp.target.display()
-                        );
-                        // Signal grandchild to exit, then cleanup
-                        let _ = nix::unistd::write(&pipe_write, b"x");
-                        let _ = nix::sys::signal::kill(grandchild, nix::sys::signal::SIGTERM);
-                        let _ = waitpid(grandchild, None);
-                        std::process::exit(126);
-                    }
+                let attached = mounts.attach(&mut mounts.lock_state(), mp, source_fd);
+                if let Err(e) = attached {
+                    eprintln!(
+                        "fuse-access-guard: FUSE mount failed on {}: {e}\n\
+                         Hint: ensure fuse3 is installed (apt install fuse3 libfuse3-dev)",
+                        mp.target.display()
+                    );
+                    // Signal grandchild to exit, then cleanup
+                    let _ = nix::unistd::write(&pipe_write, b"x");
+                    let _ = nix::sys::signal::kill(grandchild, nix::sys::signal::SIGTERM);
+                    let _ = waitpid(grandchild, None);
+                    std::process::exit(126);
                 }
             }
 
-            // 8. Signal grandchild that FUSE is ready
+            // 8. Serve the control socket before the command can look for it
+            if let (Some(socket), Some(clients)) = (&config.control, clients) {
+                let control = Control {
+                    cwd: socket.cwd.clone(),
+                    mounts: Arc::clone(&mounts),
+                    rules: Arc::clone(rules),
+                    report: Arc::clone(report),
+                    subscribers: socket.subscribers.clone(),
+                };
+                control.serve(clients);
+            }
+
+            // 9. Apply changes to the settings file while the command runs
+            if let Some(watch) = config.reload.take() {
+                watch.spawn(Arc::clone(rules), Arc::clone(&mounts), Arc::clone(logger));
+            }
+
+            // 10. Signal grandchild that FUSE is ready
             let _ = nix::unistd::write(&pipe_write, b"r");
             drop(pipe_write);
 
-            // 8. Wait for grandchild to exit
+            // 11. Wait for grandchild to exit
             let exit_code = wait_for_child(grandchild);
 
-            // 9. Drop sessions to unmount FUSE
-            drop(sessions);
+            // 12. Unmount FUSE and let the session threads finish so the
+            //    filesystems are dropped (flushing audit close events). A
+            //    leftover background process can keep a mount busy, so the
+            //    wait is bounded.
+            let sessions = mounts.take_sessions();
+            let (done_tx, done_rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                for session in sessions {
+                    session.join();
+                }
+                let _ = done_tx.send(());
+            });
+            let _ = done_rx.recv_timeout(UNMOUNT_TIMEOUT);
+
+            // 13. Write pending log summaries and emit the access report
+            if let Ok(mut logger) = logger.lock() {
+                logger.flush();
+            }
+            finish_report(report, exit_code);
             std::process::exit(exit_code);
         }
         Err(e) => {
@@ -216,6 +528,13 @@ fn fuse_daemon_process(
     }
 }
 
+/// Returns false if the socket at `path` would be reached through one of
+/// `mount_points`. Connecting needs the socket's own inode, which the FUSE
+/// mount does not pass through.
+fn socket_reachable(path: &Path, mount_points: &[MountPoint]) -> bool {
+    !mount_points.iter().any(|mp| path.starts_with(&mp.target))
+}
+
 fn write_id_mappings(uid: u32, gid: u32) -> std::io::Result<()> {
     std::fs::write("/proc/self/setgroups", "deny")?;
     let uid_map = format!("{uid} {uid} 1\n");
@@ -245,6 +564,12 @@ fn exec_command(command: &str, args: &[String]) {
     std::process::exit(127);
 }
 
+fn finish_report(report: &Mutex<Report>, exit_code: i32) {
+    if let Ok(mut report) = report.lock() {
+        report.finish(exit_code);
+    }
+}
+
 fn wait_for_child(child: Pid) -> i32 {
     match waitpid(child, None) {
         Ok(WaitStatus::Exited(_, code)) => code,
@@ -261,3 +586,144 @@ fn run_command_directly(command: &str, args: &[String]) -> Result<i32, Namespace
 
     Ok(status.code().unwrap_or(1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GuardSettings, Permissions, Settings};
+    use crate::rules::AuditLevel;
+    use std::fs;
+
+    fn plan(cwd: &Path, deny: Vec<&str>) -> Vec<PathBuf> {
+        let settings = Settings {
+            permissions: Permissions {
+                deny: deny.into_iter().map(String::from).collect(),
+            },
+            access_guard: GuardSettings::default(),
+            source: None,
+        };
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+        compute_mount_points(&rules)
+            .into_iter()
+            .map(|mp| mp.target)
+            .collect()
+    }
+
+    #[test]
+    fn test_literal_ancestor() {
+        assert_eq!(
+            literal_ancestor(Path::new("/p/config/*/secret.json")),
+            Some(PathBuf::from("/p/config"))
+        );
+        assert_eq!(
+            literal_ancestor(Path::new("/p/**/.env")),
+            Some(PathBuf::from("/p"))
+        );
+        assert_eq!(
+            literal_ancestor(Path::new("/p/*.pem")),
+            Some(PathBuf::from("/p"))
+        );
+        assert_eq!(
+            literal_ancestor(Path::new("/p/a/.env")),
+            Some(PathBuf::from("/p/a"))
+        );
+    }
+
+    #[test]
+    fn test_uncovered_mount_points_include_ancestors() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(cwd.join("config")).unwrap();
+        let settings = Settings {
+            permissions: Permissions {
+                deny: vec!["Read(./config/secret)".to_string()],
+            },
+            access_guard: GuardSettings::default(),
+            source: None,
+        };
+        let mut rules = AccessRules::new(&settings, &cwd, vec![], FsView::default()).unwrap();
+        let mounted = vec![cwd.join("config")];
+        assert!(uncovered_mount_points(&rules, &mounted).is_empty());
+
+        // A rule for the parent needs a mount containing the existing one
+        rules.add_rule("Read(./x)", &cwd, false).unwrap();
+        let targets: Vec<PathBuf> = uncovered_mount_points(&rules, &mounted)
+            .into_iter()
+            .map(|mp| mp.target)
+            .collect();
+        assert_eq!(targets, vec![cwd.clone()]);
+        assert!(uncovered_mount_points(&rules, &[cwd]).is_empty());
+    }
+
+    #[test]
+    fn test_glob_mounts_literal_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("config/prod")).unwrap();
+
+        let mounts = plan(dir.path(), vec!["Read(./config/*/secret.json)"]);
+        assert_eq!(mounts, vec![dir.path().join("config")]);
+    }
+
+    #[test]
+    fn test_nested_mount_points_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+
+        let mounts = plan(
+            dir.path(),
+            vec!["Read(./a/b/secret)", "Read(./**/.env)", "Read(./a/key)"],
+        );
+        assert_eq!(mounts, vec![dir.path().to_path_buf()]);
+    }
+
+    #[test]
+    fn test_symlinked_and_real_paths_share_mount() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("real")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("link")).unwrap();
+
+        let link_rule = format!("Read({}/secret.txt)", dir.path().join("link").display());
+        let mounts = plan(&dir.path().join("real"), vec![&link_rule, "Read(./.env)"]);
+        assert_eq!(mounts, vec![dir.path().join("real")]);
+    }
+
+    #[test]
+    fn test_nonexistent_directory_mounts_nearest_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("build")).unwrap();
+
+        let mounts = plan(dir.path(), vec!["Read(./build/out/gen/.env)"]);
+        assert_eq!(mounts, vec![dir.path().join("build")]);
+
+        let mounts = plan(dir.path(), vec!["Read(./missing/*.key)"]);
+        assert_eq!(mounts, vec![dir.path().to_path_buf()]);
+    }
+
+    #[test]
+    fn test_audited_paths_are_mounted() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("keys")).unwrap();
+        let settings = Settings {
+            permissions: Permissions { deny: vec![] },
+            access_guard: GuardSettings {
+                sensitive: vec!["./keys/*.pem".to_string()],
+                ..Default::default()
+            },
+            source: None,
+        };
+        let mut rules = AccessRules::new(&settings, dir.path(), vec![], FsView::default()).unwrap();
+        let targets = |rules: &AccessRules| -> Vec<PathBuf> {
+            compute_mount_points(rules)
+                .into_iter()
+                .map(|mp| mp.target)
+                .collect()
+        };
+        assert!(targets(&rules).is_empty());
+
+        rules.enable_audit(AuditLevel::Sensitive, dir.path());
+        assert_eq!(targets(&rules), vec![dir.path().join("keys")]);
+
+        rules.enable_audit(AuditLevel::All, dir.path());
+        assert_eq!(targets(&rules), vec![dir.path().to_path_buf()]);
+    }
+}
@@ -7,15 +7,46 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use fuser::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyOpen, ReplyStatfs, ReplyWrite, Request,
+    consts, FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite,
+    Request, TimeOrNow,
 };
 
-use crate::logger::Logger;
-use crate::rules::{AccessRules, Operation};
+use crate::logger::{AccessEvent, CloseEvent, Decision, Logger};
+use crate::process::ProcessInfo;
+use crate::redact::Redactor;
+use crate::report::{MountCounters, Report};
+use crate::rules::{AccessRules, DenyRule, InodeId, Operation, SharedRules};
+use crate::scanner::{SecretKind, SecretScanner};
 
 const TTL: Duration = Duration::from_secs(1);
 
+/// `__FMODE_EXEC`: set by the kernel in the open flags of execve(2) and
+/// uselib(2) opens
+const FMODE_EXEC: i32 = 0o40;
+
+/// An open file on the source directory
+struct FileHandle {
+    fd: RawFd,
+    /// Virtual path the file was opened by
+    path: PathBuf,
+    /// Writes must land at end-of-file (`Append(...)` rule)
+    append_only: bool,
+    /// Set for audited paths (`--audit`)
+    audit: Option<HandleAudit>,
+    /// Content served instead of the file's (`Redact(...)` rules and
+    /// `accessGuard.substitute`); `fd` is -1 for substitutes
+    content: Option<Arc<Vec<u8>>>,
+}
+
+/// Byte counters of an audited file handle, reported at release
+struct HandleAudit {
+    /// The process that opened the file
+    process: ProcessInfo,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
 pub struct PassthroughFs {
     /// The original source directory path (for deny rule matching)
     source_dir: PathBuf,
@@ -28,19 +59,31 @@ pub struct PassthroughFs {
     inodes: HashMap<u64, PathBuf>,
     path_to_inode: HashMap<PathBuf, u64>,
     next_inode: u64,
-    /// File handle -> (raw fd, virtual path)
-    file_handles: HashMap<u64, (RawFd, PathBuf)>,
+    file_handles: HashMap<u64, FileHandle>,
     next_fh: u64,
-    rules: Arc<AccessRules>,
+    /// Pid of the guarded command; ancestry in denial logs stops here
+    root_pid: u32,
+    rules: Arc<SharedRules>,
     logger: Arc<Mutex<Logger>>,
+    report: Arc<Mutex<Report>>,
+    /// Read/write traffic of this mount, collected by `report` at exit
+    counters: Arc<MountCounters>,
+    /// Allowed accesses are recorded in `report` (`--learn`)
+    learning: bool,
+    /// Results of `--scan-secrets`, by inode and modification time
+    scanner: SecretScanner,
+    /// Contents of files matching `Redact(...)` rules with secrets masked
+    redactor: Redactor,
 }
 
 impl PassthroughFs {
     pub fn new(
         source_dir: PathBuf,
         source_file: std::fs::File,
-        rules: Arc<AccessRules>,
+        root_pid: u32,
+        rules: Arc<SharedRules>,
         logger: Arc<Mutex<Logger>>,
+        report: Arc<Mutex<Report>>,
     ) -> Self {
         let source_fd = source_file.as_raw_fd();
 
@@ -50,6 +93,10 @@ impl PassthroughFs {
         let root_rel = PathBuf::from("");
         inodes.insert(1, root_rel.clone());
         path_to_inode.insert(root_rel, 1);
+        let (counters, learning) = {
+            let mut report = report.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (report.mount_counters(&source_dir), report.is_learning())
+        };
 
         PassthroughFs {
             source_dir,
@@ -60,11 +107,22 @@ impl PassthroughFs {
             next_inode: 2,
             file_handles: HashMap::new(),
             next_fh: 1,
+            root_pid,
             rules,
             logger,
+            report,
+            counters,
+            learning,
+            scanner: SecretScanner::default(),
+            redactor: Redactor::default(),
         }
     }
 
+    /// The current rule set; rules may be replaced while mounted
+    fn rules(&self) -> Arc<AccessRules> {
+        self.rules.load()
+    }
+
     fn get_or_create_inode(&mut self, rel_path: &Path) -> u64 {
         if let Some(&ino) = self.path_to_inode.get(rel_path) {
             return ino;
@@ -76,15 +134,41 @@ impl PassthroughFs {
         ino
     }
 
+    /// Drop the inode mapping of a path that no longer exists
+    fn forget_path(&mut self, rel_path: &Path) {
+        if let Some(ino) = self.path_to_inode.remove(rel_path) {
+            self.inodes.remove(&ino);
+        }
+    }
+
+    /// Re-key the inodes of `from` and everything below it after a rename
+    fn rename_inodes(&mut self, from: &Path, to: &Path) {
+        self.forget_path(to);
+        let moved: Vec<(PathBuf, u64)> = self
+            .path_to_inode
+            .iter()
+            .filter(|(p, _)| p.starts_with(from))
+            .map(|(p, &ino)| (p.clone(), ino))
+            .collect();
+        for (old, ino) in moved {
+            self.path_to_inode.remove(&old);
+            let new = match old.strip_prefix(from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                _ => to.to_path_buf(),
+            };
+            self.inodes.insert(ino, new.clone());
+            self.path_to_inode.insert(new, ino);
+        }
+    }
+
     /// Get relative path for an inode
     fn rel_path(&self, ino: u64) -> Option<&PathBuf> {
         self.inodes.get(&ino)
     }
 
-    /// Get the virtual (absolute) path for deny rule matching
-    fn virtual_path(&self, ino: u64) -> Option<PathBuf> {
-        self.rel_path(ino)
-            .map(|rel| self.source_dir.join(rel))
+    /// Get relative path for the entry `name` inside directory `parent`
+    fn child_rel_path(&self, parent: u64, name: &OsStr) -> Option<PathBuf> {
+        self.rel_path(parent).map(|p| p.join(name))
     }
 
     /// fstatat on the source_fd with the given relative path
@@ -115,12 +199,16 @@ impl PassthroughFs {
         }
     }
 
-    fn flags_to_operation(flags: i32) -> Operation {
+    fn flags_to_operations(flags: i32) -> &'static [Operation] {
+        if flags & FMODE_EXEC != 0 {
+            return &[Operation::Read, Operation::Execute];
+        }
         let access_mode = flags & libc::O_ACCMODE;
         match access_mode {
-            libc::O_RDONLY => Operation::Read,
-            libc::O_WRONLY | libc::O_RDWR => Operation::Write,
-            _ => Operation::Read,
+            libc::O_RDONLY => &[Operation::Read],
+            libc::O_WRONLY => &[Operation::Write],
+            libc::O_RDWR => &[Operation::Read, Operation::Write],
+            _ => &[Operation::Read],
         }
     }
 
@@ -128,6 +216,494 @@ impl PassthroughFs {
         let path = format!("/proc/{}/exe", pid);
         std::fs::read_link(path).ok()
     }
+
+    fn is_caller_excluded(&self, req: &Request<'_>) -> bool {
+        if let Some(exe) = Self::get_caller_executable(req.pid()) {
+            self.rules().is_executable_excluded(&exe)
+        } else {
+            false
+        }
+    }
+
+    /// Log a denial of `op` on `virtual_p` by `rule`, returning true if the
+    /// operation must fail with `errno`. Accesses matching rules that are not
+    /// enforced are logged as `WOULD_DENY` and allowed.
+    fn deny(
+        &self,
+        req: &Request<'_>,
+        virtual_p: &Path,
+        op: Operation,
+        rule: Option<&DenyRule>,
+        errno: i32,
+    ) -> bool {
+        self.deny_with_reason(req, virtual_p, op, rule, None, errno)
+    }
+
+    /// Like `deny()`, for denials not caused by a rule, logged with `reason`
+    fn deny_with_reason(
+        &self,
+        req: &Request<'_>,
+        virtual_p: &Path,
+        op: Operation,
+        rule: Option<&DenyRule>,
+        reason: Option<&str>,
+        errno: i32,
+    ) -> bool {
+        let rules = self.rules();
+        let enforced = rule.map_or(rules.is_enforcing(), |rule| rules.enforces(rule));
+        let decision = if enforced {
+            Decision::Deny
+        } else {
+            Decision::WouldDeny
+        };
+        let path = virtual_p.to_string_lossy();
+        if !self.coalesced(req, &path, op, decision) {
+            let process = ProcessInfo::resolve(req.pid(), req.uid(), self.root_pid);
+            self.log_decision(&AccessEvent {
+                process: &process,
+                path: &path,
+                op,
+                decision,
+                rule,
+                reason,
+                errno: enforced.then_some(errno),
+            });
+        }
+        enforced
+    }
+
+    /// Returns true if the logger coalesced this event with an identical
+    /// recent one, in which case it is only counted in the report and the
+    /// calling process is not resolved again
+    fn coalesced(&self, req: &Request<'_>, path: &str, op: Operation, decision: Decision) -> bool {
+        let repeat = match self.logger.lock() {
+            Ok(mut logger) => logger.coalesce(req.pid(), path, op, decision),
+            Err(_) => false,
+        };
+        if repeat {
+            if let Ok(mut report) = self.report.lock() {
+                report.record_repeat(path, req.pid(), decision);
+            }
+        }
+        repeat
+    }
+
+    /// Log a denied, would-be denied or substituted access and record it in
+    /// the report
+    fn log_decision(&self, event: &AccessEvent<'_>) {
+        if let Ok(mut logger) = self.logger.lock() {
+            logger.log_access(event);
+        }
+        if let Ok(mut report) = self.report.lock() {
+            if event.decision == Decision::WouldDeny {
+                report.record_would_deny(event.path, event.process);
+            } else {
+                report.record_denial(event.path, event.process);
+            }
+        }
+    }
+
+    /// Record an allowed `op` on `rel` for `--learn`
+    fn learn(&self, rel: &Path, op: Operation) {
+        if !self.learning {
+            return;
+        }
+        if let Ok(mut report) = self.report.lock() {
+            report.record_access(&self.source_dir.join(rel), op);
+        }
+    }
+
+    /// Log an allowed open of `virtual_p` for `ops` if the path is audited,
+    /// returning the audit state for the new file handle
+    fn audit_open(
+        &self,
+        req: &Request<'_>,
+        virtual_p: &Path,
+        ops: &[Operation],
+    ) -> Option<HandleAudit> {
+        if !self.rules().is_audited(virtual_p) {
+            return None;
+        }
+
+        let process = ProcessInfo::resolve(req.pid(), req.uid(), self.root_pid);
+        if let Ok(mut logger) = self.logger.lock() {
+            for &op in ops {
+                logger.log_access(&AccessEvent {
+                    process: &process,
+                    path: &virtual_p.to_string_lossy(),
+                    op,
+                    decision: Decision::Allow,
+                    rule: None,
+                    reason: None,
+                    errno: None,
+                });
+            }
+        }
+        Some(HandleAudit {
+            process,
+            bytes_read: 0,
+            bytes_written: 0,
+        })
+    }
+
+    /// The rule denying `op` on `rel` to the calling process, matched by path
+    /// and, when inode protection is enabled, by inode identity. Excluded
+    /// executables are never denied.
+    fn denying_rule(&self, req: &Request<'_>, rel: &Path, op: Operation) -> Option<DenyRule> {
+        if self.is_caller_excluded(req) {
+            return None;
+        }
+
+        let rules = self.rules();
+        rules
+            .matching_rule(&self.source_dir.join(rel), op)
+            .or_else(|| {
+                let stat = self.stat_relative(rel).ok()?;
+                rules.inode_rule(inode_id(&stat), op)
+            })
+            .cloned()
+    }
+
+    /// Returns true if the metadata of `rel` is hidden from the calling
+    /// process: the decision `check_denied` makes for `Stat`, without logging
+    fn is_stat_masked(&self, req: &Request<'_>, rel: &Path) -> bool {
+        self.denying_rule(req, rel, Operation::Stat)
+            .is_some_and(|rule| self.rules().enforces(&rule))
+    }
+
+    /// Check `op` on `rel` for the calling process, logging denials
+    fn check_denied(&self, req: &Request<'_>, rel: &Path, op: Operation) -> bool {
+        match self.denying_rule(req, rel, op) {
+            Some(rule) => {
+                let virtual_p = self.source_dir.join(rel);
+                self.deny(req, &virtual_p, op, Some(&rule), libc::EACCES)
+            }
+            None => false,
+        }
+    }
+
+    /// With `--scan-secrets`, look for credentials in the file opened for
+    /// reading as `fd`. Executions and excluded executables are not scanned.
+    fn scan_for_secret(&mut self, req: &Request<'_>, fd: RawFd, flags: i32) -> Option<SecretKind> {
+        let max_size = self.rules().secret_scan_limit()?;
+        if flags & FMODE_EXEC != 0
+            || flags & libc::O_ACCMODE == libc::O_WRONLY
+            || self.is_caller_excluded(req)
+        {
+            return None;
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            return None;
+        }
+        self.scanner.scan_fd(fd, &stat, max_size)
+    }
+
+    /// The `Append(...)` rule a writable open of `rel` with `flags` is
+    /// subject to
+    fn append_only_rule(&self, req: &Request<'_>, rel: &Path, flags: i32) -> Option<DenyRule> {
+        if flags & libc::O_ACCMODE == libc::O_RDONLY {
+            return None;
+        }
+        self.denying_rule(req, rel, Operation::Overwrite)
+    }
+
+    /// The enforced `Redact(...)` rule matching the file `rel`, with
+    /// metadata `stat`, for the calling process. Excluded executables see
+    /// the real content.
+    fn redacting_rule(&self, req: &Request<'_>, rel: &Path, stat: &libc::stat) -> Option<DenyRule> {
+        if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+            return None;
+        }
+        let rules = self.rules();
+        let rule = rules.redacting_rule(&self.source_dir.join(rel), Some(inode_id(stat)))?;
+        (rules.enforces(rule) && !self.is_caller_excluded(req)).then(|| rule.clone())
+    }
+
+    /// The enforced rule with a substitute denying the calling process to
+    /// read the file `rel`, with metadata `stat`
+    fn substitute_rule(&self, req: &Request<'_>, rel: &Path, stat: &libc::stat) -> Option<DenyRule> {
+        let rules = self.rules();
+        let rule = rules
+            .matching_rule(&self.source_dir.join(rel), Operation::Read)
+            .or_else(|| rules.inode_rule(inode_id(stat), Operation::Read))?;
+        let applies = rule.substitute.is_some() && rules.enforces(rule);
+        (applies && !self.is_caller_excluded(req)).then(|| rule.clone())
+    }
+
+    /// Attributes of `rel` as seen by the calling process, with the time
+    /// they may be cached for. Metadata hidden by `Stat` rules is masked.
+    /// Substituted files report the size of the content served instead,
+    /// redacted files that of their last redaction.
+    fn visible_attr(
+        &mut self,
+        req: &Request<'_>,
+        rel: &Path,
+        ino: u64,
+        stat: &libc::stat,
+    ) -> (Duration, FileAttr) {
+        if self.is_stat_masked(req, rel) {
+            return (Duration::ZERO, masked_attr(ino, stat));
+        }
+        let mut attr = stat_to_attr(ino, stat);
+        if let Some(rule) = self.substitute_rule(req, rel, stat) {
+            let path = self.source_dir.join(rel);
+            let size = self.rules().substitute_content(&rule, &path).map_or(0, |c| c.len());
+            attr.size = size as u64;
+            attr.blocks = attr.size.div_ceil(512);
+            return (Duration::ZERO, attr);
+        }
+        if self.redacting_rule(req, rel, stat).is_none() {
+            return (TTL, attr);
+        }
+        // Redacting happens on open; reads are direct I/O and end at the
+        // end of the content served, whatever size is reported here
+        if let Some(len) = self.redactor.cached_len(stat) {
+            attr.size = len as u64;
+            attr.blocks = attr.size.div_ceil(512);
+        }
+        // Excluded executables see a different size
+        (Duration::ZERO, attr)
+    }
+
+    fn insert_handle(
+        &mut self,
+        fd: RawFd,
+        path: PathBuf,
+        append_only: bool,
+        audit: Option<HandleAudit>,
+        content: Option<Arc<Vec<u8>>>,
+    ) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let handle = FileHandle {
+            fd,
+            path,
+            append_only,
+            audit,
+            content,
+        };
+        self.file_handles.insert(fh, handle);
+        fh
+    }
+
+    /// Close a file handle, logging its byte counts if it was audited
+    fn close_handle(&self, handle: FileHandle) {
+        if handle.fd >= 0 {
+            unsafe { libc::close(handle.fd) };
+        }
+        if let Some(audit) = handle.audit {
+            let event = CloseEvent {
+                process: &audit.process,
+                path: &handle.path.to_string_lossy(),
+                bytes_read: audit.bytes_read,
+                bytes_written: audit.bytes_written,
+            };
+            if let Ok(mut logger) = self.logger.lock() {
+                logger.log_close(&event);
+            }
+        }
+    }
+
+    fn handle_audit(&mut self, fh: u64) -> Option<&mut HandleAudit> {
+        self.file_handles.get_mut(&fh)?.audit.as_mut()
+    }
+
+    /// Check whether `rel` is hidden from the calling process, logging the
+    /// attempted `op` if so. Hidden entries behave as if they did not exist,
+    /// except that an entry of the same name cannot be created in their place.
+    /// Excluded executables still see them.
+    fn check_hidden(&self, req: &Request<'_>, rel: &Path, op: Operation) -> bool {
+        let virtual_p = self.source_dir.join(rel);
+        let rules = self.rules();
+        let rule = match rules.hiding_rule(&virtual_p) {
+            Some(rule) if !self.is_caller_excluded(req) => rule,
+            _ => return false,
+        };
+        // Only creating a hidden name reveals that it is taken
+        let errno = if op == Operation::Stat {
+            libc::ENOENT
+        } else {
+            libc::EACCES
+        };
+        self.deny(req, &virtual_p, op, Some(rule), errno)
+    }
+
+    /// Check whether giving `from` the additional or new name `to` would let
+    /// a guarded file escape its deny rules. Denied links fail with EPERM,
+    /// renames with EACCES.
+    fn check_relocation_denied(
+        &self,
+        req: &Request<'_>,
+        from: &Path,
+        to: &Path,
+        link: bool,
+    ) -> bool {
+        if self.is_caller_excluded(req) {
+            return false;
+        }
+
+        let from_virtual = self.source_dir.join(from);
+        let to_virtual = self.source_dir.join(to);
+        let stat = self.stat_relative(from).ok();
+        let is_dir = stat.is_some_and(|st| (st.st_mode & libc::S_IFMT) == libc::S_IFDIR);
+        let rules = self.rules();
+        let rule = rules
+            .relocation_rule(&from_virtual, &to_virtual, is_dir)
+            .or_else(|| {
+                let st = stat.filter(|_| link)?;
+                rules.guarding_rule(inode_id(&st))
+            });
+        let Some(rule) = rule else {
+            return false;
+        };
+        let (op, errno) = if link {
+            (Operation::Create, libc::EPERM)
+        } else {
+            (Operation::Rename, libc::EACCES)
+        };
+        self.deny(req, &from_virtual, op, Some(rule), errno)
+    }
+
+    /// Read the entries of directory `rel` (inode `ino`), including `.` and
+    /// `..`, omitting entries hidden from the calling process
+    fn read_dir_entries(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        rel: &Path,
+    ) -> Result<Vec<(u64, FileType, String)>, i32> {
+        // Open directory via openat
+        let dir_fd = self.open_relative(rel, libc::O_RDONLY | libc::O_DIRECTORY)?;
+
+        // Use fdopendir to read directory entries
+        let dirp = unsafe { libc::fdopendir(dir_fd) };
+        if dirp.is_null() {
+            unsafe { libc::close(dir_fd) };
+            return Err(libc::EIO);
+        }
+
+        // Resolved lazily: only needed once a hidden entry is encountered
+        let mut excluded: Option<bool> = None;
+        let rules = self.rules();
+        let mut entries: Vec<(u64, FileType, String)> = Vec::new();
+        entries.push((ino, FileType::Directory, ".".to_string()));
+        entries.push((if ino == 1 { 1 } else { ino }, FileType::Directory, "..".to_string()));
+
+        loop {
+            unsafe { *libc::__errno_location() = 0 };
+            let entry = unsafe { libc::readdir(dirp) };
+            if entry.is_null() {
+                break;
+            }
+
+            let d_name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = d_name.to_string_lossy().to_string();
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            let child_rel = rel.join(&name);
+            if rules.is_hidden(&self.source_dir.join(&child_rel))
+                && !*excluded.get_or_insert_with(|| self.is_caller_excluded(req))
+            {
+                continue;
+            }
+            let child_ino = self.get_or_create_inode(&child_rel);
+            if self.learning {
+                if let Ok(mut report) = self.report.lock() {
+                    report.record_seen(&self.source_dir.join(&child_rel));
+                }
+            }
+
+            let d_type = unsafe { (*entry).d_type };
+            let file_type = match d_type {
+                libc::DT_DIR => FileType::Directory,
+                libc::DT_LNK => FileType::Symlink,
+                _ => FileType::RegularFile,
+            };
+
+            entries.push((child_ino, file_type, name));
+        }
+
+        unsafe { libc::closedir(dirp) };
+        Ok(entries)
+    }
+
+    /// unlinkat `parent`/`name`; `flags` is 0 for files or AT_REMOVEDIR
+    fn remove_entry(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        flags: libc::c_int,
+        reply: ReplyEmpty,
+    ) {
+        let rel = match self.child_rel_path(parent, name) {
+            Some(p) => p,
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        if self.check_denied(req, &rel, Operation::Delete) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let c_path = path_to_cstring(&rel);
+        if unsafe { libc::unlinkat(self.source_fd, c_path.as_ptr(), flags) } != 0 {
+            reply.error(errno());
+            return;
+        }
+        self.learn(&rel, Operation::Delete);
+        self.forget_path(&rel);
+        reply.ok();
+    }
+
+    /// fstatat the newly created `rel` and reply with its entry
+    fn reply_new_entry(&mut self, rel: &Path, reply: ReplyEntry) {
+        self.learn(rel, Operation::Create);
+        match self.stat_relative(rel) {
+            Ok(stat) => {
+                let ino = self.get_or_create_inode(rel);
+                reply.entry(&TTL, &stat_to_attr(ino, &stat), 0);
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+}
+
+fn inode_id(stat: &libc::stat) -> InodeId {
+    InodeId {
+        dev: stat.st_dev,
+        ino: stat.st_ino,
+    }
+}
+
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+fn time_or_now_to_timespec(time: Option<TimeOrNow>) -> libc::timespec {
+    match time {
+        None => libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        Some(TimeOrNow::Now) => libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_NOW,
+        },
+        Some(TimeOrNow::SpecificTime(t)) => {
+            let dur = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+            libc::timespec {
+                tv_sec: dur.as_secs() as libc::time_t,
+                tv_nsec: dur.subsec_nanos() as libc::c_long,
+            }
+        }
+    }
 }
 
 fn path_to_cstring(path: &Path) -> CString {
@@ -140,6 +716,29 @@ fn path_to_cstring(path: &Path) -> CString {
     }
 }
 
+/// Attributes revealing nothing but the entry's type and permission bits,
+/// which the kernel checks before executing or entering it
+fn masked_attr(ino: u64, stat: &libc::stat) -> FileAttr {
+    let real = stat_to_attr(ino, stat);
+    FileAttr {
+        ino,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: real.kind,
+        perm: real.perm,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
 fn stat_to_attr(ino: u64, stat: &libc::stat) -> FileAttr {
     let kind = match stat.st_mode & libc::S_IFMT {
         libc::S_IFDIR => FileType::Directory,
@@ -167,6 +766,14 @@ fn stat_to_attr(ino: u64, stat: &libc::stat) -> FileAttr {
 }
 
 impl Filesystem for PassthroughFs {
+    fn init(&mut self, _req: &Request<'_>, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        // Let the kernel use READDIRPLUS where supported; both readdir and
+        // readdirplus enforce List rules.
+        let _ =
+            config.add_capabilities(consts::FUSE_DO_READDIRPLUS | consts::FUSE_READDIRPLUS_AUTO);
+        Ok(())
+    }
+
     fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
         let parent_rel = match self.rel_path(parent) {
             Some(p) => p.clone(),
@@ -178,11 +785,25 @@ impl Filesystem for PassthroughFs {
 
         let child_rel = parent_rel.join(name);
 
+        if self.check_hidden(_req, &child_rel, Operation::Stat) {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        // Don't let the kernel cache hidden entries seen by excluded executables
+        let ttl = if self.rules().is_hidden(&self.source_dir.join(&child_rel)) {
+            Duration::ZERO
+        } else {
+            TTL
+        };
+
         match self.stat_relative(&child_rel) {
             Ok(stat) => {
+                self.learn(&child_rel, Operation::Stat);
                 let ino = self.get_or_create_inode(&child_rel);
-                let attr = stat_to_attr(ino, &stat);
-                reply.entry(&TTL, &attr, 0);
+                // Stat-denied entries still resolve, with masked attributes,
+                // so that they can be opened
+                let (attr_ttl, attr) = self.visible_attr(_req, &child_rel, ino, &stat);
+                reply.entry(&ttl.min(attr_ttl), &attr, 0);
             }
             Err(e) => reply.error(e),
         }
@@ -197,10 +818,17 @@ impl Filesystem for PassthroughFs {
             }
         };
 
+        if self.check_hidden(_req, &rel, Operation::Stat) {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        // Only logged here: the attributes are masked rather than refused
+        self.check_denied(_req, &rel, Operation::Stat);
+
         match self.stat_relative(&rel) {
             Ok(stat) => {
-                let attr = stat_to_attr(ino, &stat);
-                reply.attr(&TTL, &attr);
+                let (ttl, attr) = self.visible_attr(_req, &rel, ino, &stat);
+                reply.attr(&ttl, &attr);
             }
             Err(e) => reply.error(e),
         }
@@ -216,37 +844,118 @@ impl Filesystem for PassthroughFs {
         };
 
         let virtual_p = self.source_dir.join(&rel);
-        let op = Self::flags_to_operation(flags);
 
-        // Check if executable is excluded
-        let excluded = if let Some(exe) = Self::get_caller_executable(_req.pid()) {
-             self.rules.is_executable_excluded(&exe)
-        } else {
-             false
+        // Reads denied by rules with a substitute are served its content,
+        // logged loudly, instead of failing
+        let read_only = flags & libc::O_ACCMODE == libc::O_RDONLY && flags & FMODE_EXEC == 0;
+        let substitute = match self.stat_relative(&rel) {
+            Ok(stat) if read_only => self.substitute_rule(_req, &rel, &stat),
+            _ => None,
         };
+        if let Some(rule) = substitute {
+            let content = self.rules().substitute_content(&rule, &virtual_p);
+            let reason = rule.substitute.as_ref().map(|with| format!("substitute:{}", with.name()));
+            let path = virtual_p.to_string_lossy();
+            if !self.coalesced(_req, &path, Operation::Read, Decision::Substitute) {
+                let process = ProcessInfo::resolve(_req.pid(), _req.uid(), self.root_pid);
+                self.log_decision(&AccessEvent {
+                    process: &process,
+                    path: &path,
+                    op: Operation::Read,
+                    decision: Decision::Substitute,
+                    rule: Some(&rule),
+                    reason: reason.as_deref(),
+                    errno: None,
+                });
+            }
+            let fh = self.insert_handle(-1, virtual_p, false, None, content);
+            reply.opened(fh, consts::FOPEN_DIRECT_IO);
+            return;
+        }
+
+        // Executables are mapped only up to the size reported, which is
+        // masked for Stat-denied files
+        if flags & FMODE_EXEC != 0 && self.is_stat_masked(_req, &rel) {
+            let rule = self.denying_rule(_req, &rel, Operation::Stat);
+            let reason = Some("stat:masked");
+            let op = Operation::Execute;
+            if self.deny_with_reason(_req, &virtual_p, op, rule.as_ref(), reason, libc::EACCES) {
+                reply.error(libc::EACCES);
+                return;
+            }
+        }
 
         // Check access rules
-        if !excluded && self.rules.is_denied(&virtual_p, op) {
-            if let Ok(mut logger) = self.logger.lock() {
-                logger.log_denied(
-                    _req.pid(),
-                    &format!("pid:{}", _req.pid()),
-                    &virtual_p.to_string_lossy(),
-                    op,
-                );
+        for &op in Self::flags_to_operations(flags) {
+            if self.check_denied(_req, &rel, op) {
+                reply.error(libc::EACCES);
+                return;
             }
-            reply.error(libc::EACCES);
+        }
+
+        // Append-only files may only be opened for writing with O_APPEND,
+        // matching chattr +a
+        let append_rule = self.append_only_rule(_req, &rel, flags);
+        if append_rule.is_some()
+            && flags & libc::O_APPEND == 0
+            && self.deny(_req, &virtual_p, Operation::Overwrite, append_rule.as_ref(), libc::EPERM)
+        {
+            reply.error(libc::EPERM);
             return;
         }
+        let append_only = append_rule.is_some();
 
         // Open the real file using openat (bypasses FUSE mount)
         let open_flags = flags & (libc::O_ACCMODE | libc::O_APPEND | libc::O_NONBLOCK);
         match self.open_relative(&rel, open_flags) {
             Ok(fd) => {
-                let fh = self.next_fh;
-                self.next_fh += 1;
-                self.file_handles.insert(fh, (fd, virtual_p));
-                reply.opened(fh, 0);
+                let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+                let redact_rule = match unsafe { libc::fstat(fd, &mut stat) } {
+                    0 => self.redacting_rule(_req, &rel, &stat),
+                    _ => None,
+                };
+                let mut redacted = None;
+                if let Some(ref rule) = redact_rule {
+                    let max_size = self.rules().redact_limit();
+                    redacted = self.redactor.redact_fd(fd, &stat, max_size);
+                    // Files too large to redact in memory are not served
+                    if redacted.is_none() {
+                        let reason = Some("redact:too-large");
+                        let op = Operation::Read;
+                        self.deny_with_reason(_req, &virtual_p, op, Some(rule), reason, libc::EACCES);
+                        unsafe { libc::close(fd) };
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                }
+                // Redacted content is safe to read whatever the file holds
+                let secret = match redacted {
+                    Some(_) => None,
+                    None => self.scan_for_secret(_req, fd, flags),
+                };
+                if let Some(secret) = secret {
+                    let (op, reason) = (Operation::Read, Some(secret.reason()));
+                    if self.deny_with_reason(_req, &virtual_p, op, None, reason, libc::EACCES) {
+                        unsafe { libc::close(fd) };
+                        reply.error(libc::EACCES);
+                        return;
+                    }
+                }
+                let ops = Self::flags_to_operations(flags);
+                for &op in ops {
+                    self.learn(&rel, op);
+                }
+                let audit = self.audit_open(_req, &virtual_p, ops);
+                // Bypass the page cache, which excluded executables fill
+                // with the real content. Reads of files whose size is masked
+                // must not stop at the size reported.
+                let open_flags = if redacted.is_some() || self.is_stat_masked(_req, &rel) {
+                    consts::FOPEN_DIRECT_IO
+                } else {
+                    0
+                };
+                let fh = self.insert_handle(fd, virtual_p, append_only, audit, redacted);
+                reply.opened(fh, open_flags);
             }
             Err(e) => reply.error(e),
         }
@@ -263,8 +972,8 @@ impl Filesystem for PassthroughFs {
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        let fd = match self.file_handles.get(&fh) {
-            Some((fd, _)) => *fd,
+        let (fd, content) = match self.file_handles.get(&fh) {
+            Some(handle) => (handle.fd, handle.content.clone()),
             None => {
                 reply.error(libc::EBADF);
                 return;
@@ -272,8 +981,22 @@ impl Filesystem for PassthroughFs {
         };
 
         let mut buf = vec![0u8; size as usize];
-        let n = unsafe { libc::pread(fd, buf.as_mut_ptr() as *mut libc::c_void, size as usize, offset) };
+        let n = match content {
+            Some(content) => {
+                let start = (offset.max(0) as usize).min(content.len());
+                let end = start.saturating_add(size as usize).min(content.len());
+                buf[..end - start].copy_from_slice(&content[start..end]);
+                (end - start) as isize
+            }
+            None => unsafe {
+                libc::pread(fd, buf.as_mut_ptr() as *mut libc::c_void, size as usize, offset)
+            },
+        };
         if n >= 0 {
+            if let Some(audit) = self.handle_audit(fh) {
+                audit.bytes_read += n as u64;
+            }
+            self.counters.record_read(n as u64);
             buf.truncate(n as usize);
             reply.data(&buf);
         } else {
@@ -283,7 +1006,7 @@ impl Filesystem for PassthroughFs {
 
     fn write(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         _ino: u64,
         fh: u64,
         offset: i64,
@@ -293,16 +1016,37 @@ impl Filesystem for PassthroughFs {
         _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
-        let fd = match self.file_handles.get(&fh) {
-            Some((fd, _)) => *fd,
+        let handle = match self.file_handles.get(&fh) {
+            Some(handle) => handle,
             None => {
                 reply.error(libc::EBADF);
                 return;
             }
         };
+        let fd = handle.fd;
+
+        if handle.append_only {
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+                reply.error(errno());
+                return;
+            }
+            if offset != stat.st_size {
+                let rules = self.rules();
+                let rule = rules.matching_rule(&handle.path, Operation::Overwrite);
+                if self.deny(req, &handle.path, Operation::Overwrite, rule, libc::EPERM) {
+                    reply.error(libc::EPERM);
+                    return;
+                }
+            }
+        }
 
         let n = unsafe { libc::pwrite(fd, data.as_ptr() as *const libc::c_void, data.len(), offset) };
         if n >= 0 {
+            if let Some(audit) = self.handle_audit(fh) {
+                audit.bytes_written += n as u64;
+            }
+            self.counters.record_write(n as u64);
             reply.written(n as u32);
         } else {
             reply.error(unsafe { *libc::__errno_location() });
@@ -319,8 +1063,8 @@ impl Filesystem for PassthroughFs {
         _flush: bool,
         reply: ReplyEmpty,
     ) {
-        if let Some((fd, _)) = self.file_handles.remove(&fh) {
-            unsafe { libc::close(fd) };
+        if let Some(handle) = self.file_handles.remove(&fh) {
+            self.close_handle(handle);
         }
         reply.ok();
     }
@@ -341,57 +1085,68 @@ impl Filesystem for PassthroughFs {
             }
         };
 
-        // Open directory via openat
-        let dir_fd = match self.open_relative(&rel, libc::O_RDONLY | libc::O_DIRECTORY) {
-            Ok(fd) => fd,
+        if self.check_denied(_req, &rel, Operation::List) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let entries = match self.read_dir_entries(_req, ino, &rel) {
+            Ok(entries) => entries,
             Err(e) => {
                 reply.error(e);
                 return;
             }
         };
 
-        // Use fdopendir to read directory entries
-        let dirp = unsafe { libc::fdopendir(dir_fd) };
-        if dirp.is_null() {
-            unsafe { libc::close(dir_fd) };
-            reply.error(libc::EIO);
-            return;
-        }
-
-        let mut entries: Vec<(u64, FileType, String)> = Vec::new();
-        entries.push((ino, FileType::Directory, ".".to_string()));
-        entries.push((if ino == 1 { 1 } else { ino }, FileType::Directory, "..".to_string()));
-
-        loop {
-            unsafe { *libc::__errno_location() = 0 };
-            let entry = unsafe { libc::readdir(dirp) };
-            if entry.is_null() {
+        for (i, (ino, kind, name)) in entries.iter().enumerate().skip(offset as usize) {
+            if reply.add(*ino, (i + 1) as i64, *kind, name) {
                 break;
             }
+        }
+        reply.ok();
+    }
 
-            let d_name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
-            let name = d_name.to_string_lossy().to_string();
-            if name == "." || name == ".." {
-                continue;
+    fn readdirplus(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        let rel = match self.rel_path(ino) {
+            Some(p) => p.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
             }
+        };
 
-            let child_rel = rel.join(&name);
-            let child_ino = self.get_or_create_inode(&child_rel);
-
-            let d_type = unsafe { (*entry).d_type };
-            let file_type = match d_type {
-                libc::DT_DIR => FileType::Directory,
-                libc::DT_LNK => FileType::Symlink,
-                _ => FileType::RegularFile,
-            };
-
-            entries.push((child_ino, file_type, name));
+        if self.check_denied(_req, &rel, Operation::List) {
+            reply.error(libc::EACCES);
+            return;
         }
 
-        unsafe { libc::closedir(dirp) };
+        let entries = match self.read_dir_entries(_req, ino, &rel) {
+            Ok(entries) => entries,
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        };
 
-        for (i, (ino, kind, name)) in entries.iter().enumerate().skip(offset as usize) {
-            if reply.add(*ino, (i + 1) as i64, *kind, name) {
+        for (i, (child_ino, _, name)) in entries.iter().enumerate().skip(offset as usize) {
+            let child_rel = if name == "." || name == ".." {
+                rel.clone()
+            } else {
+                rel.join(name)
+            };
+            // Metadata of Stat-denied entries is masked here too
+            let (ttl, attr) = match self.stat_relative(&child_rel) {
+                Ok(stat) => self.visible_attr(_req, &child_rel, *child_ino, &stat),
+                Err(_) => continue,
+            };
+            if reply.add(*child_ino, (i + 1) as i64, name, &ttl, &attr, 0) {
                 break;
             }
         }
@@ -407,6 +1162,11 @@ impl Filesystem for PassthroughFs {
             }
         };
 
+        if self.check_denied(_req, &rel, Operation::Stat) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
         let c_path = path_to_cstring(&rel);
         let mut buf = vec![0u8; libc::PATH_MAX as usize];
         let n = unsafe {
@@ -454,9 +1214,15 @@ impl Filesystem for PassthroughFs {
             }
         };
 
+        if self.check_denied(_req, &rel, Operation::List) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
         // Verify directory exists via fstatat
         match self.stat_relative(&rel) {
             Ok(stat) if (stat.st_mode & libc::S_IFMT) == libc::S_IFDIR => {
+                self.learn(&rel, Operation::List);
                 reply.opened(0, 0);
             }
             _ => reply.error(libc::ENOENT),
@@ -474,8 +1240,144 @@ impl Filesystem for PassthroughFs {
         reply.ok();
     }
 
-    fn access(&mut self, _req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
-        let virtual_p = match self.virtual_path(ino) {
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let rel = match self.rel_path(ino) {
+            Some(p) => p.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        if self.check_denied(req, &rel, Operation::Write) {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let c_path = path_to_cstring(&rel);
+
+        if let Some(mode) = mode {
+            let ret = unsafe { libc::fchmodat(self.source_fd, c_path.as_ptr(), mode, 0) };
+            if ret != 0 {
+                reply.error(errno());
+                return;
+            }
+        }
+
+        if uid.is_some() || gid.is_some() {
+            let ret = unsafe {
+                libc::fchownat(
+                    self.source_fd,
+                    c_path.as_ptr(),
+                    uid.unwrap_or(u32::MAX),
+                    gid.unwrap_or(u32::MAX),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            if ret != 0 {
+                reply.error(errno());
+                return;
+            }
+        }
+
+        if let Some(size) = size {
+            // Only growing an append-only file is allowed
+            let shrinks = self
+                .stat_relative(&rel)
+                .is_ok_and(|stat| (size as i64) < stat.st_size);
+            if let Some(rule) = self
+                .denying_rule(req, &rel, Operation::Overwrite)
+                .filter(|_| shrinks)
+            {
+                let virtual_p = self.source_dir.join(&rel);
+                if self.deny(req, &virtual_p, Operation::Overwrite, Some(&rule), libc::EPERM) {
+                    reply.error(libc::EPERM);
+                    return;
+                }
+            }
+
+            let open_fd = fh
+                .and_then(|fh| self.file_handles.get(&fh))
+                .map(|handle| handle.fd);
+            let ret = match open_fd {
+                Some(fd) => unsafe { libc::ftruncate(fd, size as libc::off_t) },
+                None => match self.open_relative(&rel, libc::O_WRONLY) {
+                    Ok(fd) => unsafe {
+                        let ret = libc::ftruncate(fd, size as libc::off_t);
+                        let saved = errno();
+                        libc::close(fd);
+                        *libc::__errno_location() = saved;
+                        ret
+                    },
+                    Err(e) => {
+                        reply.error(e);
+                        return;
+                    }
+                },
+            };
+            if ret != 0 {
+                reply.error(errno());
+                return;
+            }
+        }
+
+        if atime.is_some() || mtime.is_some() {
+            let times = [
+                time_or_now_to_timespec(atime),
+                time_or_now_to_timespec(mtime),
+            ];
+            let ret = unsafe {
+                libc::utimensat(
+                    self.source_fd,
+                    c_path.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            if ret != 0 {
+                reply.error(errno());
+                return;
+            }
+        }
+
+        self.learn(&rel, Operation::Write);
+        match self.stat_relative(&rel) {
+            Ok(stat) => {
+                let (ttl, attr) = self.visible_attr(req, &rel, ino, &stat);
+                reply.attr(&ttl, &attr);
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let rel = match self.child_rel_path(parent, name) {
             Some(p) => p,
             None => {
                 reply.error(libc::ENOENT);
@@ -483,30 +1385,238 @@ impl Filesystem for PassthroughFs {
             }
         };
 
-        // Check if executable is excluded
-        let excluded = if let Some(exe) = Self::get_caller_executable(_req.pid()) {
-             self.rules.is_executable_excluded(&exe)
-        } else {
-             false
+        if self.check_hidden(req, &rel, Operation::Create)
+            || self.check_denied(req, &rel, Operation::Create)
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let virtual_p = self.source_dir.join(&rel);
+        let c_path = path_to_cstring(&rel);
+        let append_rule = self.append_only_rule(req, &rel, flags);
+        let append_only = append_rule.is_some();
+        let mut open_flags = flags
+            & (libc::O_ACCMODE | libc::O_APPEND | libc::O_NONBLOCK | libc::O_EXCL | libc::O_TRUNC);
+        if append_rule.is_some_and(|rule| self.rules().enforces(&rule)) {
+            // Never truncate a file that appeared since lookup
+            open_flags &= !libc::O_TRUNC;
+        }
+        let fd = unsafe {
+            libc::openat(
+                self.source_fd,
+                c_path.as_ptr(),
+                open_flags | libc::O_CREAT,
+                mode as libc::c_uint,
+            )
         };
+        if fd < 0 {
+            reply.error(errno());
+            return;
+        }
 
-        if !excluded {
-            // Check deny rules
-            if mask & libc::R_OK != 0 && self.rules.is_denied(&virtual_p, Operation::Read) {
-                reply.error(libc::EACCES);
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            let e = errno();
+            unsafe { libc::close(fd) };
+            reply.error(e);
+            return;
+        }
+
+        self.learn(&rel, Operation::Create);
+        let ino = self.get_or_create_inode(&rel);
+        let audit = self.audit_open(req, &virtual_p, &[Operation::Create]);
+        let fh = self.insert_handle(fd, virtual_p, append_only, audit, None);
+        reply.created(&TTL, &stat_to_attr(ino, &stat), 0, fh, 0);
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let rel = match self.child_rel_path(parent, name) {
+            Some(p) => p,
+            None => {
+                reply.error(libc::ENOENT);
                 return;
             }
-            if mask & libc::W_OK != 0 && self.rules.is_denied(&virtual_p, Operation::Write) {
-                reply.error(libc::EACCES);
+        };
+
+        if self.check_hidden(req, &rel, Operation::Create)
+            || self.check_denied(req, &rel, Operation::Create)
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let c_path = path_to_cstring(&rel);
+        let ret = unsafe { libc::mkdirat(self.source_fd, c_path.as_ptr(), mode as libc::mode_t) };
+        if ret != 0 {
+            reply.error(errno());
+            return;
+        }
+        self.reply_new_entry(&rel, reply);
+    }
+
+    fn symlink(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        let rel = match self.child_rel_path(parent, link_name) {
+            Some(p) => p,
+            None => {
+                reply.error(libc::ENOENT);
                 return;
             }
-            if mask & libc::X_OK != 0 && self.rules.is_denied(&virtual_p, Operation::Execute) {
-                reply.error(libc::EACCES);
+        };
+
+        if self.check_hidden(req, &rel, Operation::Create)
+            || self.check_denied(req, &rel, Operation::Create)
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let c_path = path_to_cstring(&rel);
+        let c_target = path_to_cstring(target);
+        let ret = unsafe { libc::symlinkat(c_target.as_ptr(), self.source_fd, c_path.as_ptr()) };
+        if ret != 0 {
+            reply.error(errno());
+            return;
+        }
+        self.reply_new_entry(&rel, reply);
+    }
+
+    fn link(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let (from, to) = match (self.rel_path(ino), self.child_rel_path(newparent, newname)) {
+            (Some(from), Some(to)) => (from.clone(), to),
+            _ => {
+                reply.error(libc::ENOENT);
                 return;
             }
+        };
+
+        if self.check_relocation_denied(req, &from, &to, true) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        if self.check_hidden(req, &to, Operation::Create)
+            || self.check_denied(req, &to, Operation::Create)
+        {
+            reply.error(libc::EACCES);
+            return;
         }
 
-        // Check real filesystem access via faccessat
+        let c_from = path_to_cstring(&from);
+        let c_to = path_to_cstring(&to);
+        let ret = unsafe {
+            libc::linkat(
+                self.source_fd,
+                c_from.as_ptr(),
+                self.source_fd,
+                c_to.as_ptr(),
+                0,
+            )
+        };
+        if ret != 0 {
+            reply.error(errno());
+            return;
+        }
+        self.reply_new_entry(&to, reply);
+    }
+
+    fn unlink(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.remove_entry(req, parent, name, 0, reply);
+    }
+
+    fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.remove_entry(req, parent, name, libc::AT_REMOVEDIR, reply);
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let (from, to) = match (
+            self.child_rel_path(parent, name),
+            self.child_rel_path(newparent, newname),
+        ) {
+            (Some(from), Some(to)) => (from, to),
+            _ => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        if self.check_hidden(req, &to, Operation::Create)
+            || self.check_relocation_denied(req, &from, &to, false)
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+        // Moving onto an existing name overwrites it, and unlinks the file
+        // that was there; otherwise it creates the name
+        let target_exists = self.stat_relative(&to).is_ok();
+        let to_op = if target_exists {
+            Operation::Overwrite
+        } else {
+            Operation::Create
+        };
+        if self.check_denied(req, &from, Operation::Rename)
+            || self.check_denied(req, &to, to_op)
+            || (target_exists && self.check_denied(req, &to, Operation::Delete))
+        {
+            reply.error(libc::EACCES);
+            return;
+        }
+
+        let c_from = path_to_cstring(&from);
+        let c_to = path_to_cstring(&to);
+        let ret = unsafe {
+            libc::renameat2(
+                self.source_fd,
+                c_from.as_ptr(),
+                self.source_fd,
+                c_to.as_ptr(),
+                flags,
+            )
+        };
+        if ret != 0 {
+            reply.error(errno());
+            return;
+        }
+        self.learn(&from, Operation::Rename);
+        self.learn(&to, to_op);
+        if target_exists {
+            self.learn(&to, Operation::Delete);
+        }
+        self.rename_inodes(&from, &to);
+        reply.ok();
+    }
+
+    fn access(&mut self, req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
         let rel = match self.rel_path(ino) {
             Some(p) => p.clone(),
             None => {
@@ -514,6 +1624,21 @@ impl Filesystem for PassthroughFs {
                 return;
             }
         };
+
+        // Check deny rules
+        let checks = [
+            (libc::R_OK, Operation::Read),
+            (libc::W_OK, Operation::Write),
+            (libc::X_OK, Operation::Execute),
+        ];
+        for (bit, op) in checks {
+            if mask & bit != 0 && self.check_denied(req, &rel, op) {
+                reply.error(libc::EACCES);
+                return;
+            }
+        }
+
+        // Check real filesystem access via faccessat
         let c_path = path_to_cstring(&rel);
         let ret = unsafe { libc::faccessat(self.source_fd, c_path.as_ptr(), mask, 0) };
         if ret == 0 {
@@ -526,9 +1651,11 @@ impl Filesystem for PassthroughFs {
 
 impl Drop for PassthroughFs {
     fn drop(&mut self) {
-        // Close all open file handles
-        for (_, (fd, _)) in self.file_handles.drain() {
-            unsafe { libc::close(fd) };
+        // Close all open file handles. Releases still in flight when the
+        // command exits never arrive, so audited handles are logged here.
+        let handles: Vec<FileHandle> = self.file_handles.drain().map(|(_, h)| h).collect();
+        for handle in handles {
+            self.close_handle(handle);
         }
     }
 }
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+/// Stop walking the parent chain after this many ancestors
+const MAX_ANCESTORS: usize = 32;
+
+/// Identity of a process that triggered a denial, read from /proc
+#[derive(Debug, Clone, Default)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub uid: u32,
+    /// Short name from /proc/<pid>/comm
+    pub comm: Option<String>,
+    pub exe: Option<PathBuf>,
+    pub cmdline: Vec<String>,
+    /// Parent chain, nearest first, as (pid, comm), ending at the guarded
+    /// root command
+    pub ancestors: Vec<(u32, String)>,
+}
+
+impl ProcessInfo {
+    /// Resolve `pid` (running as `uid`) and its ancestors up to and
+    /// including `root_pid`. Fields of processes that have already exited are
+    /// left empty.
+    pub fn resolve(pid: u32, uid: u32, root_pid: u32) -> Self {
+        let mut ancestors = Vec::new();
+        let mut current = pid;
+        while current != root_pid && ancestors.len() < MAX_ANCESTORS {
+            match parent_pid(current) {
+                Some(ppid) if ppid > 1 => {
+                    ancestors.push((ppid, comm(ppid).unwrap_or_else(|| "?".to_string())));
+                    current = ppid;
+                }
+                _ => break,
+            }
+        }
+
+        ProcessInfo {
+            pid,
+            uid,
+            comm: comm(pid),
+            exe: std::fs::read_link(format!("/proc/{pid}/exe")).ok(),
+            cmdline: cmdline(pid),
+            ancestors,
+        }
+    }
+}
+
+fn comm(pid: u32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end_matches('\n').to_string())
+}
+
+fn cmdline(pid: u32) -> Vec<String> {
+    std::fs::read(format!("/proc/{pid}/cmdline"))
+        .map(|raw| {
+            raw.split(|&b| b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Identifier of the mount namespace `pid` belongs to, e.g. `mnt:[4026531841]`
+pub fn mount_namespace(pid: u32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/ns/mnt")).ok()
+}
+
+/// Pids of the other processes in the mount namespace of the current one
+pub fn namespace_peers() -> Vec<u32> {
+    let own = std::process::id();
+    let namespace = mount_namespace(own);
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|&pid| pid != own && namespace.is_some() && mount_namespace(pid) == namespace)
+        .collect()
+}
+
+/// Working directory of `pid` and the paths of its open file descriptors
+pub fn held_paths(pid: u32) -> Vec<PathBuf> {
+    let cwd = std::fs::read_link(format!("/proc/{pid}/cwd"));
+    let mut paths: Vec<PathBuf> = cwd.into_iter().collect();
+    if let Ok(fds) = std::fs::read_dir(format!("/proc/{pid}/fd")) {
+        // Pipes, sockets and the like read as e.g. `pipe:[1234]`
+        paths.extend(
+            fds.flatten()
+                .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+                .filter(|path| path.is_absolute()),
+        );
+    }
+    paths
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The comm field may contain spaces and parentheses; fields after the
+    // last ')' are "state ppid ..."
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_held_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = std::fs::File::create(dir.path().join("open")).unwrap();
+        let paths = held_paths(std::process::id());
+        assert!(paths.contains(&std::env::current_dir().unwrap()));
+        assert!(paths.contains(&std::fs::canonicalize(dir.path().join("open")).unwrap()));
+        assert!(paths.iter().all(|path| path.is_absolute()));
+        drop(file);
+
+        // Only other processes are listed
+        assert!(!namespace_peers().contains(&std::process::id()));
+    }
+
+    #[test]
+    fn test_resolve_self() {
+        let pid = std::process::id();
+        let info = ProcessInfo::resolve(pid, 0, pid);
+        assert_eq!(info.pid, pid);
+        assert!(info.comm.is_some());
+        assert_eq!(info.exe, std::env::current_exe().ok());
+        assert!(!info.cmdline.is_empty());
+        assert!(info.ancestors.is_empty());
+    }
+
+    #[test]
+    fn test_ancestors_end_at_root() {
+        let pid = std::process::id();
+        let parent = parent_pid(pid).unwrap();
+        let info = ProcessInfo::resolve(pid, 0, parent);
+        assert_eq!(info.ancestors.len(), 1);
+        assert_eq!(info.ancestors[0].0, parent);
+    }
+
+    #[test]
+    fn test_mount_namespace() {
+        let ns = mount_namespace(std::process::id()).unwrap();
+        assert!(ns.to_string_lossy().starts_with("mnt:["));
+        assert_eq!(mount_namespace(u32::MAX), None);
+    }
+
+    #[test]
+    fn test_exited_process() {
+        let info = ProcessInfo::resolve(u32::MAX, 0, 1);
+        assert!(info.comm.is_none());
+        assert!(info.exe.is_none());
+        assert!(info.cmdline.is_empty());
+        assert!(info.ancestors.is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nix::fcntl::{openat, OFlag};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::stat::Mode;
+
+use crate::config::{self, Settings};
+use crate::logger::{Logger, ReloadEvent};
+use crate::namespace::{bypassed_mounts, Mounts};
+use crate::rules::{AccessRules, RuleParseError, SharedRules};
+
+/// Pause after a change before re-reading, so that writers can finish
+const RELOAD_DELAY: Duration = Duration::from_millis(100);
+
+/// Builds a rule set from settings, applying the command line options
+pub type RuleBuilder = Box<dyn Fn(&Settings) -> Result<AccessRules, RuleParseError> + Send>;
+
+/// Re-reads the settings file when it changes and replaces the rules
+pub struct SettingsWatch {
+    /// Directory holding the settings file, opened before any mount so that
+    /// watching and reading it never go through the FUSE mounts
+    dir: File,
+    /// The settings file, as reported in logs
+    path: PathBuf,
+    build: RuleBuilder,
+    /// Whether rules dropped from the file are removed
+    allow_removals: bool,
+}
+
+impl SettingsWatch {
+    pub fn new(path: &Path, build: RuleBuilder) -> std::io::Result<Self> {
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        Ok(SettingsWatch {
+            dir: File::open(parent)?,
+            path: path.to_path_buf(),
+            build,
+            allow_removals: true,
+        })
+    }
+
+    /// Whether rules dropped from the file are removed. Unless the settings
+    /// file is protected, the guarded command may have rewritten it, so
+    /// removals are refused until restart, as `remove_rule` refuses them.
+    pub fn allow_removals(mut self, allow: bool) -> Self {
+        self.allow_removals = allow;
+        self
+    }
+
+    /// Watch the settings file on a background thread. Changed rules replace
+    /// `rules`, and directories they need are added to `mounts`.
+    pub fn spawn(self, rules: Arc<SharedRules>, mounts: Arc<Mounts>, logger: Arc<Mutex<Logger>>) {
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_DELETE;
+        // The magic link resolves to the directory below any mount
+        let dir = format!("/proc/self/fd/{}", self.dir.as_raw_fd());
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC)
+            .and_then(|inotify| inotify.add_watch(dir.as_str(), flags).map(|_| inotify));
+        let inotify = match inotify {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                eprintln!(
+                    "fuse-access-guard: cannot watch {} for changes: {e}",
+                    self.path.display()
+                );
+                return;
+            }
+        };
+
+        std::thread::spawn(move || {
+            while let Ok(events) = inotify.read_events() {
+                let name = self.path.file_name();
+                if events.iter().any(|event| event.name.as_deref() == name) {
+                    std::thread::sleep(RELOAD_DELAY);
+                    self.reload(&rules, &mounts, &logger);
+                }
+            }
+        });
+    }
+
+    /// Re-read the settings file and install the rules built from it. Rules
+    /// added through the control socket are kept, and so are removed ones
+    /// unless removals are allowed. If the file cannot be read or parsed, the
+    /// current rules stay in effect.
+    fn reload(&self, rules: &SharedRules, mounts: &Mounts, logger: &Mutex<Logger>) {
+        let built = self
+            .read()
+            .and_then(|settings| (self.build)(&settings).map_err(|e| e.to_string()));
+        let new = match built {
+            Ok(new) => new,
+            Err(error) => {
+                self.log(logger, &[], &[], &[], &[], Some(&error));
+                return;
+            }
+        };
+
+        let (added, removed, refused) = rules.update(|current| {
+            let mut new = new;
+            for rule in current.rules().iter().filter(|rule| rule.source.is_none()) {
+                new.push_rule(rule.clone());
+            }
+            let refused = if self.allow_removals {
+                Vec::new()
+            } else {
+                new.keep_rules_of(current)
+            };
+            let (added, removed) = current.diff(&new);
+            *current = new;
+            (added, removed, refused)
+        });
+        if added.is_empty() && removed.is_empty() && refused.is_empty() {
+            return;
+        }
+        let mounted = mounts.extend(&rules.load()).unwrap_or_else(|e| {
+            eprintln!("fuse-access-guard: failed to mount for new rules: {e}");
+            Vec::new()
+        });
+        for dir in bypassed_mounts(&mounted) {
+            eprintln!(
+                "fuse-access-guard: {} was mounted while the command works inside it; \
+                 paths relative to its working directory or open files bypass the new rules",
+                dir.display()
+            );
+        }
+        self.log(logger, &added, &removed, &refused, &mounted, None);
+    }
+
+    fn read(&self) -> Result<Settings, String> {
+        let name = self.path.file_name().unwrap_or_default();
+        let flags = OFlag::O_RDONLY | OFlag::O_CLOEXEC;
+        let fd = openat(Some(self.dir.as_raw_fd()), name, flags, Mode::empty())
+            .map_err(|e| format!("failed to read settings file: {e}"))?;
+        let mut content = String::new();
+        unsafe { File::from_raw_fd(fd) }
+            .read_to_string(&mut content)
+            .map_err(|e| format!("failed to read settings file: {e}"))?;
+        config::parse_settings(&content, &self.path).map_err(|e| e.to_string())
+    }
+
+    fn log(
+        &self,
+        logger: &Mutex<Logger>,
+        added: &[String],
+        removed: &[String],
+        refused: &[String],
+        mounted: &[PathBuf],
+        error: Option<&str>,
+    ) {
+        if let Ok(mut logger) = logger.lock() {
+            logger.log_reload(&ReloadEvent {
+                source: &self.path,
+                added,
+                removed,
+                refused,
+                mounted,
+                error,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_view::FsView;
+    use crate::logger::{open_sinks, FileOptions, SinkSpec};
+    use crate::report::Report;
+    use crate::rules::Operation;
+    use std::fs;
+    use std::time::Instant;
+
+    struct Session {
+        dir: tempfile::TempDir,
+        cwd: PathBuf,
+        rules: Arc<SharedRules>,
+        mounts: Arc<Mounts>,
+        logger: Arc<Mutex<Logger>>,
+    }
+
+    impl Session {
+        fn start(settings: &str) -> (Session, SettingsWatch) {
+            let dir = tempfile::tempdir().unwrap();
+            let cwd = fs::canonicalize(dir.path()).unwrap();
+            fs::create_dir(cwd.join(".claude")).unwrap();
+            let path = config::settings_path(&cwd);
+            fs::write(&path, settings).unwrap();
+
+            let build_cwd = cwd.clone();
+            let build: RuleBuilder = Box::new(move |settings| {
+                AccessRules::new(settings, &build_cwd, vec![], FsView::default())
+            });
+            let initial = build(&config::load_settings(&cwd).unwrap()).unwrap();
+            let rules = Arc::new(SharedRules::new(initial));
+            let sinks = open_sinks(
+                &[SinkSpec::File(cwd.join("guard.log"))],
+                FileOptions::default(),
+            )
+            .unwrap();
+            let logger = Arc::new(Mutex::new(Logger::new(sinks)));
+            let report = Arc::new(Mutex::new(Report::new(false, None)));
+            let fs = FsView::default();
+            let mounts = Mounts::new(0, Arc::clone(&rules), Arc::clone(&logger), report, fs);
+            mounts.assume_mounted(cwd.clone());
+
+            let watch = SettingsWatch::new(&path, build).unwrap();
+            let session = Session {
+                dir,
+                cwd,
+                rules,
+                mounts: Arc::new(mounts),
+                logger,
+            };
+            (session, watch)
+        }
+
+        fn write_settings(&self, content: &str) {
+            fs::write(config::settings_path(self.dir.path()), content).unwrap();
+        }
+
+        fn reload(&self, watch: &SettingsWatch) {
+            watch.reload(&self.rules, &self.mounts, &self.logger);
+        }
+
+        fn denies(&self, name: &str, op: Operation) -> bool {
+            self.rules.load().is_denied(&self.cwd.join(name), op)
+        }
+
+        fn log(&self) -> String {
+            fs::read_to_string(self.cwd.join("guard.log")).unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_reload_replaces_rules() {
+        let (session, watch) = Session::start(r#"{"permissions":{"deny":["Read(./.env)"]}}"#);
+        session
+            .rules
+            .update(|rules| rules.add_rule("Read(./runtime)", &session.cwd, false))
+            .unwrap();
+
+        session.write_settings(r#"{"permissions":{"deny":["Read(./id_rsa)"]}}"#);
+        session.reload(&watch);
+        assert!(session.denies("id_rsa", Operation::Read));
+        assert!(!session.denies(".env", Operation::Read));
+        assert!(session.denies("runtime", Operation::Read));
+        let log = session.log();
+        assert!(log.starts_with("[RELOADED] "), "log: {log}");
+        assert!(log.contains(" added=Read(./id_rsa) removed=Read(./.env) mounted=-"));
+
+        // Unchanged rules are not logged again
+        session.reload(&watch);
+        assert_eq!(session.log().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_refused_removals_stay_in_effect() {
+        let (session, watch) = Session::start(r#"{"permissions":{"deny":["Read(./.env)"]}}"#);
+        let watch = watch.allow_removals(false);
+
+        session.write_settings(r#"{"permissions":{"deny":["Read(./id_rsa)"]}}"#);
+        session.reload(&watch);
+        assert!(session.denies("id_rsa", Operation::Read));
+        assert!(session.denies(".env", Operation::Read));
+        let log = session.log();
+        assert!(
+            log.contains(" added=Read(./id_rsa) removed=- mounted=- refused=Read(./.env)"),
+            "log: {log}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings_keep_rules() {
+        let (session, watch) = Session::start(r#"{"permissions":{"deny":["Read(./.env)"]}}"#);
+
+        session.write_settings(r#"{"permissions":{"deny":["#);
+        session.reload(&watch);
+        assert!(session.denies(".env", Operation::Read));
+        session.write_settings(r#"{"permissions":{"deny":["Bogus(./.env)"]}}"#);
+        session.reload(&watch);
+        assert!(session.denies(".env", Operation::Read));
+        fs::remove_file(config::settings_path(&session.cwd)).unwrap();
+        session.reload(&watch);
+        assert!(session.denies(".env", Operation::Read));
+
+        let log = session.log();
+        assert_eq!(log.matches("[RELOAD_FAILED] ").count(), 3, "log: {log}");
+        assert!(log.contains("unknown operation: Bogus"));
+    }
+
+    #[test]
+    fn test_watch_detects_changes() {
+        let (session, watch) = Session::start(r#"{"permissions":{"deny":[]}}"#);
+        watch.spawn(
+            Arc::clone(&session.rules),
+            Arc::clone(&session.mounts),
+            Arc::clone(&session.logger),
+        );
+
+        // Replace the file the way editors do
+        let tmp = session.cwd.join(".claude/settings.json.tmp");
+        fs::write(&tmp, r#"{"permissions":{"deny":["Write(./Cargo.lock)"]}}"#).unwrap();
+        fs::rename(&tmp, config::settings_path(&session.cwd)).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.denies("Cargo.lock", Operation::Write) {
+            assert!(Instant::now() < deadline, "rules were not reloaded");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
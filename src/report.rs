@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde_json::json;
+
+use crate::learn::Learner;
+use crate::logger::{Decision, LogFormat};
+use crate::process::ProcessInfo;
+use crate::rules::Operation;
+
+/// Denials of a single path
+#[derive(Debug, Default)]
+struct DeniedPath {
+    count: u64,
+    /// Executable (or process name) -> number of denials
+    executables: BTreeMap<String, u64>,
+}
+
+/// Read/write traffic through a single mount, counted by its FUSE session
+/// without locking the report
+#[derive(Debug, Default)]
+pub struct MountCounters {
+    reads: AtomicU64,
+    bytes_read: AtomicU64,
+    writes: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl MountCounters {
+    pub fn record_read(&self, bytes: u64) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, bytes: u64) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Reads, bytes read, writes and bytes written so far
+    fn load(&self) -> [u64; 4] {
+        [&self.reads, &self.bytes_read, &self.writes, &self.bytes_written]
+            .map(|counter| counter.load(Ordering::Relaxed))
+    }
+}
+
+/// Access statistics accumulated by the FUSE daemon, emitted once the
+/// guarded command exits
+pub struct Report {
+    summary: bool,
+    report_file: Option<File>,
+    format: LogFormat,
+    denials: BTreeMap<String, DeniedPath>,
+    /// Accesses matching deny rules that were not enforced
+    would_deny: BTreeMap<String, DeniedPath>,
+    /// Counters of each mount, shared with its FUSE session
+    mounts: BTreeMap<PathBuf, Arc<MountCounters>>,
+    /// Executable of each process recorded, for repeats recorded by pid
+    executables: HashMap<u32, String>,
+    /// Records every allowed access with `--learn`
+    learner: Option<Learner>,
+}
+
+impl Report {
+    pub fn new(summary: bool, report_file: Option<File>) -> Self {
+        Report {
+            summary,
+            report_file,
+            format: LogFormat::default(),
+            denials: BTreeMap::new(),
+            would_deny: BTreeMap::new(),
+            mounts: BTreeMap::new(),
+            executables: HashMap::new(),
+            learner: None,
+        }
+    }
+
+    /// Format of the report file; the stderr summary is always text
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Propose a policy from the recorded accesses at the end of the run
+    pub fn with_learner(mut self, learner: Learner) -> Self {
+        self.learner = Some(learner);
+        self
+    }
+
+    /// Returns true if allowed accesses are recorded (`--learn`)
+    pub fn is_learning(&self) -> bool {
+        self.learner.is_some()
+    }
+
+    /// Record an allowed `op` on `path` for `--learn`
+    pub fn record_access(&mut self, path: &Path, op: Operation) {
+        if let Some(ref mut learner) = self.learner {
+            learner.record(path, op);
+        }
+    }
+
+    /// Record an entry seen in a directory listing for `--learn`
+    pub fn record_seen(&mut self, path: &Path) {
+        if let Some(ref mut learner) = self.learner {
+            learner.record_seen(path);
+        }
+    }
+
+    pub fn record_denial(&mut self, path: &str, process: &ProcessInfo) {
+        let executable = self.executable(process);
+        record_path(&mut self.denials, path, executable);
+    }
+
+    /// Record an access that a deny rule matched but did not block
+    pub fn record_would_deny(&mut self, path: &str, process: &ProcessInfo) {
+        let executable = self.executable(process);
+        record_path(&mut self.would_deny, path, executable);
+    }
+
+    /// Record a denial or would-be denial by `pid` that the logger coalesced
+    /// with an earlier one, without resolving the process again
+    pub fn record_repeat(&mut self, path: &str, pid: u32, decision: Decision) {
+        let executable = self.executables.get(&pid).cloned();
+        let executable = executable.unwrap_or_else(|| "?".to_string());
+        let paths = match decision {
+            Decision::WouldDeny => &mut self.would_deny,
+            _ => &mut self.denials,
+        };
+        record_path(paths, path, executable);
+    }
+
+    /// Name `process` is reported under, remembered for its repeats
+    fn executable(&mut self, process: &ProcessInfo) -> String {
+        let executable = match (&process.exe, &process.comm) {
+            (Some(exe), _) => exe.to_string_lossy().into_owned(),
+            (None, Some(comm)) => comm.clone(),
+            (None, None) => "?".to_string(),
+        };
+        self.executables.insert(process.pid, executable.clone());
+        executable
+    }
+
+    /// Counters for the traffic through `mount`, collected when the report
+    /// is emitted; sessions mounting the same directory share them
+    pub fn mount_counters(&mut self, mount: &Path) -> Arc<MountCounters> {
+        Arc::clone(self.mounts.entry(mount.to_path_buf()).or_default())
+    }
+
+    /// Mounts that saw any traffic, with reads, bytes read, writes and
+    /// bytes written
+    fn mount_traffic(&self) -> impl Iterator<Item = (&PathBuf, [u64; 4])> {
+        self.mounts
+            .iter()
+            .map(|(mount, counters)| (mount, counters.load()))
+            .filter(|(_, traffic)| traffic.iter().any(|&n| n > 0))
+    }
+
+    /// Print the summary and write the report file, as configured
+    pub fn finish(&mut self, exit_code: i32) {
+        if let Some(ref mut learner) = self.learner {
+            learner.finish();
+        }
+        if self.summary {
+            eprint!("{}", self.to_text(exit_code));
+        }
+        if self.report_file.is_some() {
+            let content = match self.format {
+                LogFormat::Text => self.to_text(exit_code),
+                LogFormat::Json => format!("{}\n", self.to_json(exit_code)),
+            };
+            if let Some(ref mut file) = self.report_file {
+                let _ = file.write_all(content.as_bytes());
+            }
+        }
+    }
+
+    fn to_text(&self, exit_code: i32) -> String {
+        let mut out = format!("fuse-access-guard: access report (exit code {exit_code})\n");
+        out.push_str(&paths_text("denied", &self.denials));
+        if !self.would_deny.is_empty() {
+            out.push_str(&paths_text("would deny", &self.would_deny));
+        }
+        out.push_str("mounts:\n");
+        for (mount, [reads, bytes_read, writes, bytes_written]) in self.mount_traffic() {
+            out.push_str(&format!(
+                "  {}  reads={} ({} bytes) writes={} ({} bytes)\n",
+                mount.display(),
+                reads,
+                bytes_read,
+                writes,
+                bytes_written
+            ));
+        }
+        out
+    }
+
+    fn to_json(&self, exit_code: i32) -> serde_json::Value {
+        let mut value = self.counters();
+        value["exit_code"] = exit_code.into();
+        value
+    }
+
+    /// Denials and mount traffic so far, as in the JSON report
+    pub fn counters(&self) -> serde_json::Value {
+        let mounts: Vec<_> = self
+            .mount_traffic()
+            .map(|(mount, [reads, bytes_read, writes, bytes_written])| {
+                json!({
+                    "path": mount.to_string_lossy(),
+                    "reads": reads,
+                    "bytes_read": bytes_read,
+                    "writes": writes,
+                    "bytes_written": bytes_written,
+                })
+            })
+            .collect();
+        json!({
+            "denied": paths_json(&self.denials),
+            "would_deny": paths_json(&self.would_deny),
+            "mounts": mounts,
+        })
+    }
+}
+
+fn record_path(paths: &mut BTreeMap<String, DeniedPath>, path: &str, executable: String) {
+    let denied = paths.entry(path.to_string()).or_default();
+    denied.count += 1;
+    *denied.executables.entry(executable).or_default() += 1;
+}
+
+/// Denied paths, most denied first
+fn sorted_paths(paths: &BTreeMap<String, DeniedPath>) -> Vec<(&String, &DeniedPath)> {
+    let mut sorted: Vec<_> = paths.iter().collect();
+    sorted.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+    sorted
+}
+
+/// `label: N access(es) to M path(s)` followed by a line per path
+fn paths_text(label: &str, paths: &BTreeMap<String, DeniedPath>) -> String {
+    let total: u64 = paths.values().map(|d| d.count).sum();
+    let mut out = format!("{label}: {total} access(es) to {} path(s)\n", paths.len());
+    for (path, denied) in sorted_paths(paths) {
+        let executables: Vec<String> = sorted_counts(&denied.executables)
+            .into_iter()
+            .map(|(exe, n)| format!("{exe} x{n}"))
+            .collect();
+        out.push_str(&format!(
+            "  {:>6}  {path}  [{}]\n",
+            denied.count,
+            executables.join(", ")
+        ));
+    }
+    out
+}
+
+fn paths_json(paths: &BTreeMap<String, DeniedPath>) -> Vec<serde_json::Value> {
+    sorted_paths(paths)
+        .into_iter()
+        .map(|(path, denied)| {
+            let executables: Vec<_> = sorted_counts(&denied.executables)
+                .into_iter()
+                .map(|(exe, n)| json!({ "executable": exe, "count": n }))
+                .collect();
+            json!({ "path": path, "count": denied.count, "executables": executables })
+        })
+        .collect()
+}
+
+/// Entries of a count map, highest count first
+fn sorted_counts(counts: &BTreeMap<String, u64>) -> Vec<(&str, u64)> {
+    let mut entries: Vec<_> = counts.iter().map(|(k, &n)| (k.as_str(), n)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek};
+
+    fn process(exe: Option<&str>, comm: &str) -> ProcessInfo {
+        ProcessInfo {
+            exe: exe.map(PathBuf::from),
+            comm: Some(comm.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn sample() -> Report {
+        let mut report = Report::new(false, Some(tempfile::tempfile().unwrap()));
+        let cat = process(Some("/usr/bin/cat"), "cat");
+        let bash = process(None, "bash");
+        report.record_denial("/w/.env", &cat);
+        report.record_denial("/w/secret.txt", &bash);
+        report.record_denial("/w/.env", &bash);
+        report.record_denial("/w/.env", &cat);
+        let counters = report.mount_counters(Path::new("/w"));
+        counters.record_read(100);
+        counters.record_read(20);
+        counters.record_write(5);
+        report.mount_counters(Path::new("/idle"));
+        report
+    }
+
+    fn finish_and_read(report: &mut Report) -> String {
+        report.finish(3);
+        let file = report.report_file.as_mut().unwrap();
+        file.seek(std::io::SeekFrom::Start(0)).unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn test_text_report() {
+        let content = finish_and_read(&mut sample());
+        assert_eq!(
+            content,
+            "fuse-access-guard: access report (exit code 3)\n\
+             denied: 4 access(es) to 2 path(s)\n\
+             \x20      3  /w/.env  [/usr/bin/cat x2, bash x1]\n\
+             \x20      1  /w/secret.txt  [bash x1]\n\
+             mounts:\n\
+             \x20 /w  reads=2 (120 bytes) writes=1 (5 bytes)\n"
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        let mut report = sample().with_format(LogFormat::Json);
+        let content = finish_and_read(&mut report);
+        let value: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(value["exit_code"], 3);
+        assert_eq!(value["denied"][0]["path"], "/w/.env");
+        assert_eq!(value["denied"][0]["count"], 3);
+        assert_eq!(
+            value["denied"][0]["executables"][0]["executable"],
+            "/usr/bin/cat"
+        );
+        assert_eq!(value["denied"][0]["executables"][0]["count"], 2);
+        assert_eq!(value["denied"][1]["path"], "/w/secret.txt");
+        assert_eq!(value["mounts"][0]["path"], "/w");
+        assert_eq!(value["mounts"][0]["reads"], 2);
+        assert_eq!(value["mounts"][0]["bytes_read"], 120);
+        assert_eq!(value["mounts"][0]["writes"], 1);
+        assert_eq!(value["mounts"][0]["bytes_written"], 5);
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let mut report = Report::new(false, Some(tempfile::tempfile().unwrap()));
+        let content = finish_and_read(&mut report);
+        assert!(content.contains("denied: 0 access(es) to 0 path(s)\nmounts:\n"));
+    }
+
+    #[test]
+    fn test_would_deny_report() {
+        let mut report = sample();
+        report.record_would_deny("/w/migrations/001.sql", &process(None, "rm"));
+        let content = finish_and_read(&mut report);
+        assert!(content.contains(
+            "would deny: 1 access(es) to 1 path(s)\n\
+             \x20      1  /w/migrations/001.sql  [rm x1]\nmounts:\n"
+        ));
+        let value = report.counters();
+        assert_eq!(value["would_deny"][0]["path"], "/w/migrations/001.sql");
+        assert_eq!(value["denied"][0]["count"], 3);
+    }
+
+    #[test]
+    fn test_coalesced_repeats_counted() {
+        let mut report = Report::new(false, None);
+        let rg = ProcessInfo {
+            pid: 7,
+            ..process(Some("/usr/bin/rg"), "rg")
+        };
+        report.record_denial("/w/.env", &rg);
+        report.record_repeat("/w/.env", 7, Decision::Deny);
+        report.record_repeat("/w/.env", 8, Decision::Deny);
+        report.record_repeat("/w/a.sql", 7, Decision::WouldDeny);
+        let value = report.counters();
+        assert_eq!(value["denied"][0]["count"], 3);
+        let executables = &value["denied"][0]["executables"];
+        assert_eq!(executables[0], json!({ "executable": "/usr/bin/rg", "count": 2 }));
+        assert_eq!(executables[1], json!({ "executable": "?", "count": 1 }));
+        assert_eq!(value["would_deny"][0]["executables"][0]["executable"], "/usr/bin/rg");
+    }
+}
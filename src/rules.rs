@@ -1,26 +1,133 @@
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 
 use glob::Pattern;
 
-use crate::config::Settings;
+use crate::config::{Settings, Substitute};
+use crate::fs_view::FsView;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Operation {
     Read,
     Write,
     Execute,
+    /// Reading metadata (lookup, stat, readlink)
+    Stat,
+    /// Enumerating a directory (opendir, readdir)
+    List,
+    /// Creating a new entry (create, mkdir, symlink, link)
+    Create,
+    /// Removing an entry (unlink, rmdir)
+    Delete,
+    /// Renaming or moving an entry away from its name
+    Rename,
+    /// Modifying existing content of a file other than by appending to it
+    /// (truncating, writing before end-of-file, replacing it). Denied by
+    /// `Append(path)` rules, which make files append-only.
+    Overwrite,
 }
 
-#[derive(Debug)]
+impl Operation {
+    pub const ALL: [Operation; 9] = [
+        Operation::Read,
+        Operation::Write,
+        Operation::Execute,
+        Operation::Stat,
+        Operation::List,
+        Operation::Create,
+        Operation::Delete,
+        Operation::Rename,
+        Operation::Overwrite,
+    ];
+
+    /// Returns true if a rule for `self` also denies `op`. `Write` is an
+    /// umbrella for Create, Delete, Rename and Overwrite; like `chattr +a`,
+    /// an append-only file cannot be deleted or renamed either.
+    pub fn implies(self, op: Operation) -> bool {
+        match self {
+            Operation::Write => matches!(
+                op,
+                Operation::Write
+                    | Operation::Create
+                    | Operation::Delete
+                    | Operation::Rename
+                    | Operation::Overwrite
+            ),
+            Operation::Overwrite => matches!(
+                op,
+                Operation::Overwrite | Operation::Delete | Operation::Rename
+            ),
+            _ => self == op,
+        }
+    }
+}
+
+/// Which allowed accesses are logged in addition to denials
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AuditLevel {
+    /// Only denials
+    #[default]
+    Denied,
+    /// Also allowed opens of paths listed under `accessGuard.sensitive`
+    Sensitive,
+    /// Also every allowed open inside the mounts
+    All,
+}
+
+impl PathPattern {
+    /// The path, or for globs the pattern string as a path
+    pub fn to_path_buf(&self) -> PathBuf {
+        match self {
+            PathPattern::Exact(p) => p.clone(),
+            PathPattern::Glob(pattern, _) => PathBuf::from(pattern.as_str()),
+        }
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        match self {
+            PathPattern::Exact(p) => path == p,
+            PathPattern::Glob(pattern, opts) => {
+                pattern.matches_with(&path.to_string_lossy(), *opts)
+            }
+        }
+    }
+}
+
+/// Identity of a file independent of the path used to reach it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InodeId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+#[derive(Debug, Clone)]
 pub enum PathPattern {
     Exact(PathBuf),
     Glob(Pattern, glob::MatchOptions),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DenyRule {
     pub operation: Operation,
     pub pattern: PathPattern,
+    /// Hide matching entries from directory listings and lookups
+    pub hide: bool,
+    /// The rule as written, e.g. `Read(./.env)`
+    pub entry: String,
+    /// Settings file the rule was loaded from
+    pub source: Option<PathBuf>,
+    /// Added by `protect_settings()` rather than written in the settings
+    pub implicit: bool,
+    /// Log matching accesses as `WOULD_DENY` instead of denying them
+    pub audit_only: bool,
+    /// `Redact(path)`: reads are served with secret values masked, writes
+    /// are denied
+    pub redact: bool,
+    /// Content served instead of denying to read (`accessGuard.substitute`)
+    pub substitute: Option<Substitute>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -31,20 +138,93 @@ pub enum RuleParseError {
     UnknownOperation(String),
     #[error("invalid glob pattern: {0}")]
     InvalidGlob(#[from] glob::PatternError),
+    #[error("only Read rules can substitute content: {0}")]
+    InvalidSubstitute(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AccessRules {
     rules: Vec<DenyRule>,
     excluded_executables: Vec<PathPattern>,
+    /// Inodes of exact deny targets that existed at startup, with the
+    /// indices of the rules targeting them
+    denied_inodes: HashMap<InodeId, Vec<usize>>,
+    /// Deny by inode identity in addition to path
+    inode_protection: bool,
+    /// Other names (hardlinks) of denied inodes found by the startup scan
+    hardlink_siblings: Vec<PathBuf>,
+    /// Paths whose allowed opens are logged with `AuditLevel::Sensitive`
+    sensitive: Vec<PathPattern>,
+    audit: AuditLevel,
+    /// Directory mounted to observe every access with `AuditLevel::All`
+    audit_root: Option<PathBuf>,
+    /// Deny accesses matching rules; if false, every rule is audit-only
+    enforce: bool,
+    /// Directory mounted to record every access with `--learn`
+    learn_root: Option<PathBuf>,
+    /// Scan files of up to this many bytes opened for reading for
+    /// credentials (`--scan-secrets`)
+    secret_scan_limit: Option<u64>,
+    /// Directory mounted to scan every file read with `--scan-secrets`
+    scan_root: Option<PathBuf>,
+    /// Files matching `Redact(...)` rules larger than this many bytes are
+    /// denied instead of read into memory
+    redact_limit: u64,
+    /// Contents of the files in `accessGuard.decoyDir`, by file name
+    decoys: HashMap<OsString, Arc<Vec<u8>>>,
+    /// How the files rules name are reached while building them
+    fs: FsView,
 }
 
 impl AccessRules {
-    pub fn new(settings: &Settings, cwd: &Path, excluded_execs: Vec<String>) -> Result<Self, RuleParseError> {
+    /// Build the rules of `settings`, reaching the files they name through
+    /// `fs`
+    pub fn new(
+        settings: &Settings,
+        cwd: &Path,
+        excluded_execs: Vec<String>,
+        fs: FsView,
+    ) -> Result<Self, RuleParseError> {
         let mut rules = Vec::new();
         for entry in &settings.permissions.deny {
-            rules.push(parse_deny_rule(entry, cwd)?);
+            let mut rule = parse_deny_rule(entry, cwd, &fs)?;
+            rule.source = settings.source.clone();
+            rules.push(rule);
+        }
+        for entry in &settings.access_guard.hide {
+            let mut rule = parse_deny_rule(entry, cwd, &fs)?;
+            rule.hide = true;
+            rule.source = settings.source.clone();
+            rules.push(rule);
+        }
+        for entry in &settings.access_guard.audit_only {
+            let mut rule = parse_deny_rule(entry, cwd, &fs)?;
+            rule.audit_only = true;
+            rule.source = settings.source.clone();
+            rules.push(rule);
+        }
+        for substitute in &settings.access_guard.substitute {
+            let mut rule = parse_deny_rule(&substitute.rule, cwd, &fs)?;
+            if rule.operation != Operation::Read {
+                return Err(RuleParseError::InvalidSubstitute(substitute.rule.clone()));
+            }
+            rule.substitute = Some(substitute.with.clone());
+            rule.source = settings.source.clone();
+            rules.push(rule);
         }
+        let decoys = settings
+            .access_guard
+            .decoy_dir
+            .as_deref()
+            .map(|dir| load_decoys(&resolve_rule_path(dir, cwd, &fs), &fs))
+            .unwrap_or_default();
+
+        let sensitive = settings
+            .access_guard
+            .sensitive
+            .iter()
+            .map(|path| parse_path_pattern(path, cwd, &fs))
+            .collect::<Result<Vec<_>, _>>()?;
 
         let mut excluded_executables = Vec::new();
         // Treat excluded executables similar to deny rules but without operation prefix
@@ -71,7 +251,119 @@ impl AccessRules {
             excluded_executables.push(pattern);
         }
 
-        Ok(AccessRules { rules, excluded_executables })
+        let denied_inodes = index_inodes(&rules, &fs);
+
+        Ok(AccessRules {
+            rules,
+            excluded_executables,
+            denied_inodes,
+            inode_protection: false,
+            hardlink_siblings: Vec::new(),
+            sensitive,
+            audit: AuditLevel::Denied,
+            audit_root: None,
+            enforce: true,
+            learn_root: None,
+            secret_scan_limit: None,
+            scan_root: None,
+            redact_limit: crate::redact::DEFAULT_MAX_SIZE,
+            decoys,
+            fs,
+        })
+    }
+
+    /// How the files rules name are reached
+    pub fn fs(&self) -> &FsView {
+        &self.fs
+    }
+
+    /// All deny and hide rules, in matching order
+    pub fn rules(&self) -> &[DenyRule] {
+        &self.rules
+    }
+
+    /// Add a rule written like a settings entry, e.g. `Read(./.env)`, with
+    /// relative paths resolved against `cwd`
+    pub fn add_rule(&mut self, entry: &str, cwd: &Path, hide: bool) -> Result<(), RuleParseError> {
+        let mut rule = parse_deny_rule(entry, cwd, &self.fs)?;
+        rule.hide = hide;
+        self.push_rule(rule);
+        Ok(())
+    }
+
+    /// Append an already parsed rule
+    pub fn push_rule(&mut self, rule: DenyRule) {
+        self.rules.push(rule);
+        self.denied_inodes = index_inodes(&self.rules, &self.fs);
+    }
+
+    /// Remove every rule written as `entry`, returning how many were removed
+    pub fn remove_rule(&mut self, entry: &str) -> usize {
+        let before = self.rules.len();
+        self.rules.retain(|rule| rule.entry != entry);
+        self.denied_inodes = index_inodes(&self.rules, &self.fs);
+        before - self.rules.len()
+    }
+
+    /// Rules of `new` that are not in `self` and rules of `self` that are
+    /// not in `new`, as written (hiding rules marked with ` [hide]`,
+    /// audit-only rules with ` [audit]`, substituting rules with
+    /// ` [substitute: <with>]`)
+    pub fn diff(&self, new: &AccessRules) -> (Vec<String>, Vec<String>) {
+        let labels = |rules: &AccessRules| -> Vec<String> {
+            rules.rules.iter().map(diff_label).collect()
+        };
+        let (old, new) = (labels(self), labels(new));
+        let added = new.iter().filter(|l| !old.contains(l)).cloned().collect();
+        let removed = old.iter().filter(|l| !new.contains(l)).cloned().collect();
+        (added, removed)
+    }
+
+    /// Append the rules of `old` that are not in `self`, so that nothing
+    /// `old` denies is allowed, returning them as labelled by `diff`
+    pub fn keep_rules_of(&mut self, old: &AccessRules) -> Vec<String> {
+        let labels: Vec<String> = self.rules.iter().map(diff_label).collect();
+        let kept: Vec<&DenyRule> = old
+            .rules
+            .iter()
+            .filter(|rule| !labels.contains(&diff_label(rule)))
+            .collect();
+        for rule in &kept {
+            self.push_rule((*rule).clone());
+        }
+        kept.into_iter().map(diff_label).collect()
+    }
+
+    /// Deny changing, deleting or renaming every settings file that
+    /// contributed rules, and deleting or renaming the directory holding it,
+    /// so that the guarded command cannot loosen the rules of later runs.
+    pub fn protect_settings(&mut self) {
+        let mut sources: Vec<PathBuf> = self.rules.iter().filter_map(|rule| rule.source.clone()).collect();
+        sources.sort();
+        sources.dedup();
+
+        for source in sources {
+            let file = canonicalize_rule_path(&source, &self.fs);
+            let mut implicit = vec![(Operation::Write, "Write", file.clone())];
+            if let Some(dir) = file.parent() {
+                implicit.push((Operation::Delete, "Delete", dir.to_path_buf()));
+                implicit.push((Operation::Rename, "Rename", dir.to_path_buf()));
+            }
+            for (operation, name, path) in implicit {
+                self.rules.push(DenyRule {
+                    operation,
+                    entry: format!("{name}({})", path.display()),
+                    pattern: PathPattern::Exact(path),
+                    hide: false,
+                    source: Some(source.clone()),
+                    implicit: true,
+                    audit_only: false,
+                    redact: false,
+                    substitute: None,
+                });
+            }
+        }
+        self.denied_inodes = index_inodes(&self.rules, &self.fs);
     }
 
     /// Returns the unique set of paths referenced by deny rules.
@@ -79,25 +371,134 @@ impl AccessRules {
     pub fn denied_paths(&self) -> Vec<PathBuf> {
         self.rules
             .iter()
-            .map(|rule| match &rule.pattern {
-                PathPattern::Exact(p) => p.clone(),
-                PathPattern::Glob(pattern, _) => PathBuf::from(pattern.as_str()),
-            })
+            .map(|rule| rule.pattern.to_path_buf())
             .collect()
     }
 
+    /// Returns true if `op` on `path` is denied by an enforced rule.
+    #[cfg(test)]
     pub fn is_denied(&self, path: &Path, op: Operation) -> bool {
-        self.rules.iter().any(|rule| {
-            if rule.operation != op {
-                return false;
-            }
-            match &rule.pattern {
-                PathPattern::Exact(p) => path == p,
-                PathPattern::Glob(pattern, opts) => {
-                    pattern.matches_with(&path.to_string_lossy(), *opts)
-                }
+        self.matching_rule(path, op)
+            .is_some_and(|rule| self.enforces(rule))
+    }
+
+    /// The first rule denying `op` on `path`, preferring enforced rules
+    pub fn matching_rule(&self, path: &Path, op: Operation) -> Option<&DenyRule> {
+        self.preferring_enforced(
+            self.rules
+                .iter()
+                .filter(|rule| rule.operation.implies(op) && rule.pattern.matches(path)),
+        )
+    }
+
+    /// The first enforced rule of `rules`, or else the first rule
+    fn preferring_enforced<'a>(
+        &self,
+        mut rules: impl Iterator<Item = &'a DenyRule>,
+    ) -> Option<&'a DenyRule> {
+        let first = rules.next()?;
+        if self.enforces(first) {
+            return Some(first);
+        }
+        rules.find(|rule| self.enforces(rule)).or(Some(first))
+    }
+
+    /// Hide every entry denied for reading, as if each Read rule were listed
+    /// under `accessGuard.hide`.
+    pub fn hide_read_denied(&mut self) {
+        for rule in &mut self.rules {
+            if rule.operation == Operation::Read {
+                rule.hide = true;
             }
-        })
+        }
+    }
+
+    /// Log accesses matching any rule as `WOULD_DENY` instead of denying
+    /// them (`--dry-run`)
+    pub fn set_enforce(&mut self, enforce: bool) {
+        self.enforce = enforce;
+    }
+
+    /// Returns true unless every rule is audit-only (`--dry-run`). Also
+    /// decides denials not caused by a rule.
+    pub fn is_enforcing(&self) -> bool {
+        self.enforce
+    }
+
+    /// Deny reading files of up to `max_size` bytes whose content contains
+    /// credentials. Everything below `root` is mounted so that its files
+    /// are scanned.
+    pub fn enable_secret_scan(&mut self, max_size: u64, root: &Path) {
+        self.secret_scan_limit = Some(max_size);
+        self.scan_root = Some(canonicalize_rule_path(root, &self.fs));
+    }
+
+    /// Size limit of files scanned for credentials, if scanning is enabled
+    pub fn secret_scan_limit(&self) -> Option<u64> {
+        self.secret_scan_limit
+    }
+
+    /// Deny reading files matching `Redact(...)` rules that are larger than
+    /// `max_size` bytes
+    pub fn set_redact_limit(&mut self, max_size: u64) {
+        self.redact_limit = max_size;
+    }
+
+    /// Size limit of files served redacted
+    pub fn redact_limit(&self) -> u64 {
+        self.redact_limit
+    }
+
+    /// Returns true if accesses matching `rule` are denied rather than only
+    /// logged.
+    pub fn enforces(&self, rule: &DenyRule) -> bool {
+        self.enforce && !rule.audit_only
+    }
+
+    /// Returns true if `path` matches an enforced hiding rule, so that it
+    /// must not be listed, looked up or created.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        self.hiding_rule(path).is_some_and(|rule| self.enforces(rule))
+    }
+
+    /// The first hiding rule matching `path`, preferring enforced rules
+    pub fn hiding_rule(&self, path: &Path) -> Option<&DenyRule> {
+        self.preferring_enforced(
+            self.rules
+                .iter()
+                .filter(|rule| rule.hide && rule.pattern.matches(path)),
+        )
+    }
+
+    /// The first `Redact(...)` rule matching `path` or, when inode
+    /// protection is enabled, the file identified by `id`, preferring
+    /// enforced rules
+    pub fn redacting_rule(&self, path: &Path, id: Option<InodeId>) -> Option<&DenyRule> {
+        let by_path = self
+            .rules
+            .iter()
+            .filter(|rule| rule.redact && rule.pattern.matches(path));
+        let by_inode = id
+            .filter(|_| self.inode_protection)
+            .into_iter()
+            .flat_map(|id| self.inode_rules(id))
+            .filter(|rule| rule.redact);
+        self.preferring_enforced(by_path.chain(by_inode))
+    }
+
+    /// The content served instead of `path` for reads denied by `rule`, if
+    /// the rule substitutes content
+    pub fn substitute_content(&self, rule: &DenyRule, path: &Path) -> Option<Arc<Vec<u8>>> {
+        let content = match rule.substitute.as_ref()? {
+            Substitute::Empty => Arc::default(),
+            Substitute::Stub { content } => Arc::new(content.clone().into_bytes()),
+            Substitute::Decoy => path
+                .file_name()
+                .and_then(|name| self.decoys.get(name))
+                .cloned()
+                .unwrap_or_default(),
+        };
+        Some(content)
     }
 
     pub fn is_executable_excluded(&self, exe_path: &Path) -> bool {
@@ -108,9 +509,269 @@ impl AccessRules {
             }
         })
     }
+
+    /// Enable denial by inode identity, so that a denied file is protected
+    /// under any name it is reachable by inside the mounts. `scan_roots` are
+    /// searched for hardlinks of denied files, which are reported by
+    /// `hardlink_siblings()` so that their directories can be mounted too.
+    pub fn enable_inode_protection(&mut self, scan_roots: &[PathBuf]) {
+        self.inode_protection = true;
+
+        let exact_targets: Vec<&Path> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.pattern {
+                PathPattern::Exact(p) => Some(p.as_path()),
+                PathPattern::Glob(..) => None,
+            })
+            .collect();
+        let linked: HashMap<InodeId, u64> = exact_targets
+            .iter()
+            .filter_map(|p| self.fs.metadata(p).ok())
+            .filter(|meta| meta.nlink() > 1)
+            .map(|meta| {
+                let id = InodeId {
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                };
+                (id, meta.nlink())
+            })
+            .collect();
+        if linked.is_empty() {
+            return;
+        }
+
+        let mut found = Vec::new();
+        for root in scan_roots {
+            scan_for_inodes(root, &linked, &mut found, &self.fs);
+        }
+        found.retain(|p| !exact_targets.contains(&p.as_path()));
+        found.sort();
+        found.dedup();
+        self.hardlink_siblings = found;
+    }
+
+    /// Log allowed accesses at `level`. With `AuditLevel::All`, everything
+    /// below `root` is mounted so that its accesses are observed.
+    pub fn enable_audit(&mut self, level: AuditLevel, root: &Path) {
+        self.audit = level;
+        let all = level == AuditLevel::All;
+        self.audit_root = all.then(|| canonicalize_rule_path(root, &self.fs));
+    }
+
+    /// Returns true if allowed accesses to `path` are logged.
+    pub fn is_audited(&self, path: &Path) -> bool {
+        match self.audit {
+            AuditLevel::Denied => false,
+            AuditLevel::Sensitive => self.sensitive.iter().any(|p| p.matches(path)),
+            AuditLevel::All => true,
+        }
+    }
+
+    /// Paths that must be mounted for auditing, in the form returned by
+    /// `denied_paths()`.
+    pub fn audited_paths(&self) -> Vec<PathBuf> {
+        match self.audit {
+            AuditLevel::Denied => Vec::new(),
+            AuditLevel::Sensitive => self.sensitive.iter().map(|p| p.to_path_buf()).collect(),
+            AuditLevel::All => self.audit_root.iter().map(|root| root.join("*")).collect(),
+        }
+    }
+
+    /// Mount everything below `root` so that all accesses to it can be
+    /// recorded (`--learn`)
+    pub fn enable_learning(&mut self, root: &Path) {
+        self.learn_root = Some(canonicalize_rule_path(root, &self.fs));
+    }
+
+    /// Paths that must be mounted for `--learn` and `--scan-secrets`, in
+    /// the form returned by `denied_paths()`.
+    pub fn observed_paths(&self) -> Vec<PathBuf> {
+        self.learn_root
+            .iter()
+            .chain(&self.scan_root)
+            .map(|root| root.join("*"))
+            .collect()
+    }
+
+    /// Hardlinks of denied files found by `enable_inode_protection()`.
+    pub fn hardlink_siblings(&self) -> &[PathBuf] {
+        &self.hardlink_siblings
+    }
+
+    /// If inode protection is enabled, the first rule denying `op` on the
+    /// file identified by `id`.
+    pub fn inode_rule(&self, id: InodeId, op: Operation) -> Option<&DenyRule> {
+        if !self.inode_protection {
+            return None;
+        }
+        self.preferring_enforced(self.inode_rules(id).filter(|rule| rule.operation.implies(op)))
+    }
+
+    /// The first rule whose exact target is the inode `id`, if any.
+    pub fn guarding_rule(&self, id: InodeId) -> Option<&DenyRule> {
+        self.preferring_enforced(self.inode_rules(id))
+    }
+
+    fn inode_rules(&self, id: InodeId) -> impl Iterator<Item = &DenyRule> {
+        self.denied_inodes
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.rules[index])
+    }
+
+    /// The first deny rule covering `from` that would no longer cover it if it
+    /// were given the new name `to` (by rename or hardlink). For directories,
+    /// rules that may match below `from` are considered too.
+    pub fn relocation_rule(&self, from: &Path, to: &Path, is_dir: bool) -> Option<&DenyRule> {
+        Operation::ALL.into_iter().find_map(|op| {
+            self.covering_rule(from, op, is_dir)
+                .filter(|_| self.covering_rule(to, op, is_dir).is_none())
+        })
+    }
+
+    fn covering_rule(&self, path: &Path, op: Operation, is_dir: bool) -> Option<&DenyRule> {
+        self.preferring_enforced(self.rules.iter().filter(|rule| {
+            if !rule.operation.implies(op) {
+                return false;
+            }
+            match &rule.pattern {
+                PathPattern::Exact(p) => p == path || (is_dir && p.starts_with(path)),
+                PathPattern::Glob(pattern, opts) => {
+                    pattern.matches_with(&path.to_string_lossy(), *opts)
+                        || (is_dir && glob_prefix_matches(pattern, path, *opts))
+                }
+            }
+        }))
+    }
 }
 
-fn parse_deny_rule(entry: &str, cwd: &Path) -> Result<DenyRule, RuleParseError> {
+/// Rules shared by the FUSE sessions, the control socket and the settings
+/// watcher. Changes build a new rule set that replaces the current one as a
+/// whole, so every check sees either the old or the new rules.
+#[derive(Debug)]
+pub struct SharedRules {
+    current: RwLock<Arc<AccessRules>>,
+    /// Serializes changes. Building rules may stat paths inside the mounts,
+    /// so `current` must stay readable meanwhile.
+    changing: Mutex<()>,
+}
+
+impl SharedRules {
+    pub fn new(rules: AccessRules) -> Self {
+        SharedRules {
+            current: RwLock::new(Arc::new(rules)),
+            changing: Mutex::new(()),
+        }
+    }
+
+    /// The current rule set
+    pub fn load(&self) -> Arc<AccessRules> {
+        match self.current.read() {
+            Ok(rules) => Arc::clone(&rules),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// Apply `change` to a copy of the current rules and install the result
+    pub fn update<T>(&self, change: impl FnOnce(&mut AccessRules) -> T) -> T {
+        let _changing = self.changing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut rules = AccessRules::clone(&self.load());
+        let result = change(&mut rules);
+        self.store(rules);
+        result
+    }
+
+    /// Replace the current rules
+    pub fn store(&self, rules: AccessRules) {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = Arc::new(rules);
+    }
+}
+
+/// Map the inodes of exact deny targets that exist to the indices of the
+/// rules targeting them
+fn index_inodes(rules: &[DenyRule], fs: &FsView) -> HashMap<InodeId, Vec<usize>> {
+    let mut denied_inodes: HashMap<InodeId, Vec<usize>> = HashMap::new();
+    for (index, rule) in rules.iter().enumerate() {
+        if let PathPattern::Exact(p) = &rule.pattern {
+            if let Ok(meta) = fs.metadata(p) {
+                let id = InodeId {
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                };
+                denied_inodes.entry(id).or_default().push(index);
+            }
+        }
+    }
+    denied_inodes
+}
+
+/// Returns true if the leading components of `pattern`, as many as `path`
+/// has, match `path`, i.e. the pattern may match something below `path`.
+fn glob_prefix_matches(pattern: &Pattern, path: &Path, opts: glob::MatchOptions) -> bool {
+    let depth = path.components().count();
+    let pattern_path = Path::new(pattern.as_str());
+    let prefix: Vec<Component> = pattern_path.components().take(depth + 1).collect();
+    if prefix.len() <= depth {
+        return false;
+    }
+    if prefix.iter().any(|c| c.as_os_str() == "**") {
+        return true;
+    }
+    let prefix: PathBuf = prefix[..depth].iter().collect();
+    Pattern::new(&prefix.to_string_lossy())
+        .map(|p| p.matches_with(&path.to_string_lossy(), opts))
+        .unwrap_or(false)
+}
+
+/// Recursively collect paths under `dir` whose inode is in `targets`,
+/// without following symlinks or crossing into other filesystems.
+fn scan_for_inodes(
+    dir: &Path,
+    targets: &HashMap<InodeId, u64>,
+    found: &mut Vec<PathBuf>,
+    fs: &FsView,
+) {
+    let Ok(dir_meta) = fs.symlink_metadata(dir) else {
+        return;
+    };
+    if !targets.keys().any(|id| id.dev == dir_meta.dev()) {
+        return;
+    }
+    let Ok(entries) = fs.read_dir(dir) else {
+        return;
+    };
+    for path in entries {
+        let Ok(meta) = fs.symlink_metadata(&path) else {
+            continue;
+        };
+        if meta.is_dir() {
+            scan_for_inodes(&path, targets, found, fs);
+        } else if meta.is_file() && meta.nlink() > 1 {
+            let id = InodeId {
+                dev: meta.dev(),
+                ino: meta.ino(),
+            };
+            if targets.contains_key(&id) {
+                found.push(path);
+            }
+        }
+    }
+}
+
+/// `rule` as written, marked as listed by `AccessRules::diff`
+fn diff_label(rule: &DenyRule) -> String {
+    match (rule.hide, rule.audit_only, &rule.substitute) {
+        (true, _, _) => format!("{} [hide]", rule.entry),
+        (false, true, _) => format!("{} [audit]", rule.entry),
+        (false, false, Some(with)) => format!("{} [substitute: {}]", rule.entry, with.name()),
+        (false, false, None) => rule.entry.clone(),
+    }
+}
+
+fn parse_deny_rule(entry: &str, cwd: &Path, fs: &FsView) -> Result<DenyRule, RuleParseError> {
     // Format: "Operation(path)" e.g. "Read(./a.txt)", "Write(./*.env*)"
     let open = entry
         .find('(')
@@ -130,17 +791,36 @@ fn parse_deny_rule(entry: &str, cwd: &Path) -> Result<DenyRule, RuleParseError>
         "Read" => Operation::Read,
         "Write" => Operation::Write,
         "Execute" => Operation::Execute,
+        "Stat" | "Metadata" => Operation::Stat,
+        "List" => Operation::List,
+        "Create" => Operation::Create,
+        "Delete" => Operation::Delete,
+        "Rename" => Operation::Rename,
+        "Append" => Operation::Overwrite,
+        "Redact" => Operation::Write,
         _ => return Err(RuleParseError::UnknownOperation(op_str.to_string())),
     };
 
-    // Resolve relative paths against cwd
-    let resolved = if path_str.starts_with("./") || path_str.starts_with("../") {
-        // Strip "./" prefix and join with cwd to avoid paths like "/foo/./bar"
-        let stripped = path_str.strip_prefix("./").unwrap_or(path_str);
-        cwd.join(stripped)
-    } else {
-        PathBuf::from(path_str)
-    };
+    Ok(DenyRule {
+        operation,
+        pattern: parse_path_pattern(path_str, cwd, fs)?,
+        hide: false,
+        entry: entry.to_string(),
+        source: None,
+        implicit: false,
+        audit_only: false,
+        redact: op_str == "Redact",
+        substitute: None,
+    })
+}
+
+/// Parse the path part of a rule, e.g. `./*.env*`, into a canonical pattern
+fn parse_path_pattern(
+    path_str: &str,
+    cwd: &Path,
+    fs: &FsView,
+) -> Result<PathPattern, RuleParseError> {
+    let resolved = resolve_rule_path(path_str, cwd, fs);
 
     let resolved_str = resolved.to_string_lossy();
     let has_glob = resolved_str.contains('*') || resolved_str.contains('?') || resolved_str.contains('[');
@@ -154,20 +834,85 @@ fn parse_deny_rule(entry: &str, cwd: &Path) -> Result<DenyRule, RuleParseError>
     } else {
         PathPattern::Exact(resolved)
     };
+    Ok(pattern)
+}
+
+/// Resolve a path written in the settings, e.g. `./.env`, against `cwd`
+/// and canonicalize it
+fn resolve_rule_path(path_str: &str, cwd: &Path, fs: &FsView) -> PathBuf {
+    let resolved = if path_str.starts_with("./") || path_str.starts_with("../") {
+        // Strip "./" prefix and join with cwd to avoid paths like "/foo/./bar"
+        let stripped = path_str.strip_prefix("./").unwrap_or(path_str);
+        cwd.join(stripped)
+    } else {
+        PathBuf::from(path_str)
+    };
+    canonicalize_rule_path(&resolved, fs)
+}
 
-    Ok(DenyRule { operation, pattern })
+/// Read the regular files directly inside `dir`, by file name. They are
+/// read while building the rules, as `dir` may be inside the mounts.
+fn load_decoys(dir: &Path, fs: &FsView) -> HashMap<OsString, Arc<Vec<u8>>> {
+    let Ok(entries) = fs.read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .into_iter()
+        .filter(|path| fs.symlink_metadata(path).is_ok_and(|meta| meta.is_file()))
+        .filter_map(|path| {
+            let content = fs.read(&path).ok()?;
+            Some((path.file_name()?.to_os_string(), Arc::new(content)))
+        })
+        .collect()
+}
+
+/// Canonicalize an absolute rule path so that it compares equal to the
+/// virtual paths seen by the FUSE filesystem. The longest existing prefix of
+/// the literal (glob-free) part is resolved with realpath, which follows
+/// symlinks such as a symlinked cwd; the remaining components are normalized
+/// lexically, dropping `.` and applying `..`.
+pub fn canonicalize_rule_path(path: &Path, fs: &FsView) -> PathBuf {
+    if path.is_relative() {
+        return path.to_path_buf();
+    }
+
+    let literal: PathBuf = path
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect();
+    let (mut resolved, rest) = literal
+        .ancestors()
+        .find_map(|prefix| {
+            let real = fs.canonicalize(prefix).ok()?;
+            let rest = path.strip_prefix(prefix).ok()?;
+            Some((real, rest))
+        })
+        .unwrap_or_else(|| (PathBuf::from("/"), path));
+
+    for component in rest.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    resolved
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Permissions, Settings};
+    use crate::config::{GuardSettings, Permissions, Settings};
 
     fn make_settings(deny: Vec<&str>) -> Settings {
         Settings {
             permissions: Permissions {
                 deny: deny.into_iter().map(String::from).collect(),
             },
+            access_guard: GuardSettings::default(),
+            source: None,
         }
     }
 
@@ -175,7 +920,7 @@ mod tests {
     fn test_exact_read_denied() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Read(./a.txt)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(Path::new("/home/user/project/a.txt"), Operation::Read));
         assert!(!rules.is_denied(Path::new("/home/user/project/b.txt"), Operation::Read));
@@ -185,7 +930,7 @@ mod tests {
     fn test_write_not_blocked_by_read_rule() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Read(./a.txt)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(!rules.is_denied(Path::new("/home/user/project/a.txt"), Operation::Write));
     }
@@ -194,7 +939,7 @@ mod tests {
     fn test_glob_pattern() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Read(./*.env*)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(Path::new("/home/user/project/.env"), Operation::Read));
         assert!(rules.is_denied(Path::new("/home/user/project/.env.local"), Operation::Read));
@@ -205,7 +950,7 @@ mod tests {
     fn test_write_operation() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Write(./secret.key)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(Path::new("/home/user/project/secret.key"), Operation::Write));
         assert!(!rules.is_denied(Path::new("/home/user/project/secret.key"), Operation::Read));
@@ -215,7 +960,7 @@ mod tests {
     fn test_execute_operation() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Execute(./dangerous.sh)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(
             Path::new("/home/user/project/dangerous.sh"),
@@ -223,25 +968,116 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_stat_operation() {
+        let cwd = Path::new("/home/user/project");
+        let settings = make_settings(vec!["Stat(./id_rsa)", "Metadata(./keys/*)"]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        assert!(rules.is_denied(Path::new("/home/user/project/id_rsa"), Operation::Stat));
+        assert!(rules.is_denied(Path::new("/home/user/project/keys/a"), Operation::Stat));
+        assert!(!rules.is_denied(Path::new("/home/user/project/id_rsa"), Operation::Read));
+    }
+
+    #[test]
+    fn test_list_operation() {
+        let cwd = Path::new("/home/user/project");
+        let settings = make_settings(vec!["List(./private)"]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        assert!(rules.is_denied(Path::new("/home/user/project/private"), Operation::List));
+        assert!(!rules.is_denied(Path::new("/home/user/project/private"), Operation::Read));
+        assert!(!rules.is_denied(
+            Path::new("/home/user/project/private/notes.txt"),
+            Operation::List
+        ));
+    }
+
+    #[test]
+    fn test_create_delete_rename_operations() {
+        let cwd = Path::new("/p");
+        let settings = make_settings(vec![
+            "Create(./migrations/*.sql)",
+            "Delete(./migrations/*.sql)",
+            "Delete(./.git/**)",
+            "Rename(./Cargo.toml)",
+        ]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let sql = Path::new("/p/migrations/001.sql");
+        assert!(rules.is_denied(sql, Operation::Create));
+        assert!(rules.is_denied(sql, Operation::Delete));
+        assert!(!rules.is_denied(sql, Operation::Write));
+        assert!(!rules.is_denied(sql, Operation::Rename));
+        assert!(rules.is_denied(Path::new("/p/.git/objects/ab/cd"), Operation::Delete));
+        assert!(!rules.is_denied(Path::new("/p/.git/index"), Operation::Write));
+        assert!(rules.is_denied(Path::new("/p/Cargo.toml"), Operation::Rename));
+    }
+
+    #[test]
+    fn test_write_implies_create_delete_rename() {
+        let cwd = Path::new("/p");
+        let settings = make_settings(vec!["Write(./config.json)"]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let path = Path::new("/p/config.json");
+        for op in [Operation::Create, Operation::Delete, Operation::Rename] {
+            assert!(rules.is_denied(path, op), "{op:?} not implied");
+        }
+        assert!(!rules.is_denied(path, Operation::Read));
+    }
+
+    #[test]
+    fn test_append_only_operation() {
+        let cwd = Path::new("/p");
+        let settings = make_settings(vec!["Append(./CHANGELOG.md)", "Write(./locked.log)"]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let path = Path::new("/p/CHANGELOG.md");
+        for op in [Operation::Overwrite, Operation::Delete, Operation::Rename] {
+            assert!(rules.is_denied(path, op), "{op:?} not implied");
+        }
+        assert!(!rules.is_denied(path, Operation::Write));
+        assert!(!rules.is_denied(path, Operation::Read));
+        assert!(rules.is_denied(Path::new("/p/locked.log"), Operation::Overwrite));
+    }
+
+    #[test]
+    fn test_matching_rule_records_origin() {
+        let cwd = Path::new("/p");
+        let mut settings = make_settings(vec!["Read(./*.key)", "Write(./.env)"]);
+        settings.source = Some(PathBuf::from("/p/.claude/settings.json"));
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let rule = rules
+            .matching_rule(Path::new("/p/.env"), Operation::Delete)
+            .unwrap();
+        assert_eq!(rule.entry, "Write(./.env)");
+        assert_eq!(rule.source.as_deref(), settings.source.as_deref());
+        assert!(rules
+            .matching_rule(Path::new("/p/.env"), Operation::Read)
+            .is_none());
+    }
+
     #[test]
     fn test_invalid_format() {
         let cwd = Path::new("/tmp");
         let settings = make_settings(vec!["invalid"]);
-        assert!(AccessRules::new(&settings, cwd, vec![]).is_err());
+        assert!(AccessRules::new(&settings, cwd, vec![], FsView::default()).is_err());
     }
 
     #[test]
     fn test_unknown_operation() {
         let cwd = Path::new("/tmp");
-        let settings = make_settings(vec!["Delete(./file.txt)"]);
-        assert!(AccessRules::new(&settings, cwd, vec![]).is_err());
+        let settings = make_settings(vec!["Destroy(./file.txt)"]);
+        assert!(AccessRules::new(&settings, cwd, vec![], FsView::default()).is_err());
     }
 
     #[test]
     fn test_multiple_rules() {
         let cwd = Path::new("/home/user/project");
         let settings = make_settings(vec!["Read(./a.txt)", "Read(./.env)", "Write(./config.json)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(Path::new("/home/user/project/a.txt"), Operation::Read));
         assert!(rules.is_denied(Path::new("/home/user/project/.env"), Operation::Read));
@@ -259,21 +1095,174 @@ mod tests {
     fn test_absolute_path() {
         let cwd = Path::new("/tmp");
         let settings = make_settings(vec!["Read(/etc/passwd)"]);
-        let rules = AccessRules::new(&settings, cwd, vec![]).unwrap();
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
 
         assert!(rules.is_denied(Path::new("/etc/passwd"), Operation::Read));
     }
 
+    #[test]
+    fn test_relocation_denied() {
+        let cwd = Path::new("/p");
+        let settings = make_settings(vec![
+            "Read(./config/*/secret.json)",
+            "Read(./*.env*)",
+            "Write(./keys/id_rsa)",
+        ]);
+        let rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let denied = |from: &str, to: &str, is_dir: bool| {
+            rules
+                .relocation_rule(Path::new(from), Path::new(to), is_dir)
+                .is_some()
+        };
+        assert!(denied("/p/.env", "/p/notes.txt", false));
+        assert!(!denied("/p/.env", "/p/.env.bak", false));
+        assert!(denied("/p/keys/id_rsa", "/p/keys/id_rsa.old", false));
+        assert!(denied("/p/keys", "/p/keys2", true));
+        assert!(denied("/p/config", "/p/settings", true));
+        assert!(!denied("/p/config/prod", "/p/config/staging", true));
+        assert!(!denied("/p/src", "/p/lib", true));
+        assert!(!denied("/p/notes.txt", "/p/readme.txt", false));
+    }
+
+    #[test]
+    fn test_inode_denied_only_with_protection() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".env"), "KEY=1").unwrap();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+        std::fs::hard_link(dir.path().join(".env"), dir.path().join("other/leak")).unwrap();
+
+        let settings = make_settings(vec!["Read(./.env)"]);
+        let mut rules = AccessRules::new(&settings, dir.path(), vec![], FsView::default()).unwrap();
+        let meta = std::fs::metadata(dir.path().join("other/leak")).unwrap();
+        let id = InodeId {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        };
+
+        assert!(rules.guarding_rule(id).is_some());
+        assert!(rules.inode_rule(id, Operation::Read).is_none());
+
+        rules.enable_inode_protection(&[dir.path().to_path_buf()]);
+        let rule = rules.inode_rule(id, Operation::Read).unwrap();
+        assert_eq!(rule.entry, "Read(./.env)");
+        assert!(rules.inode_rule(id, Operation::Write).is_none());
+        assert_eq!(rules.hardlink_siblings(), &[dir.path().join("other/leak")]);
+    }
+
+    #[test]
+    fn test_canonicalize_lexical_dotdot() {
+        assert_eq!(
+            canonicalize_rule_path(Path::new("/nonexistent-root/a/../b/./c"), &FsView::default()),
+            PathBuf::from("/nonexistent-root/b/c")
+        );
+        assert_eq!(
+            canonicalize_rule_path(Path::new("/nonexistent-root/x/../*.env"), &FsView::default()),
+            PathBuf::from("/nonexistent-root/*.env")
+        );
+    }
+
+    #[test]
+    fn test_symlinked_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("var/home/project");
+        std::fs::create_dir_all(&real).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("var/home"), dir.path().join("home")).unwrap();
+
+        let cwd = dir.path().join("home/project");
+        let settings = make_settings(vec!["Read(./.env)", "Read(./config/*.key)"]);
+        let rules = AccessRules::new(&settings, &cwd, vec![], FsView::default()).unwrap();
+
+        assert!(rules.is_denied(&real.join(".env"), Operation::Read));
+        assert!(rules.is_denied(&real.join("config/a.key"), Operation::Read));
+    }
+
+    #[test]
+    fn test_parent_dir_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().join("project");
+        std::fs::create_dir_all(&cwd).unwrap();
+        std::fs::create_dir_all(dir.path().join("shared")).unwrap();
+
+        let settings = make_settings(vec![
+            "Read(../shared/.env)",
+            "Read(./missing/../secret.txt)",
+            "Write(../shared/*.key)",
+        ]);
+        let rules = AccessRules::new(&settings, &cwd, vec![], FsView::default()).unwrap();
+
+        assert!(rules.is_denied(&dir.path().join("shared/.env"), Operation::Read));
+        assert!(rules.is_denied(&cwd.join("secret.txt"), Operation::Read));
+        assert!(rules.is_denied(&dir.path().join("shared/id.key"), Operation::Write));
+    }
+
+    #[test]
+    fn test_audit_levels() {
+        let cwd = Path::new("/p");
+        let mut settings = make_settings(vec!["Read(./.env)"]);
+        settings.access_guard.sensitive = vec!["./keys/*.pem".to_string()];
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let pem = Path::new("/p/keys/id.pem");
+        let readme = Path::new("/p/README.md");
+        assert!(!rules.is_audited(pem));
+        assert!(rules.audited_paths().is_empty());
+
+        rules.enable_audit(AuditLevel::Sensitive, cwd);
+        assert!(rules.is_audited(pem));
+        assert!(!rules.is_audited(readme));
+        assert_eq!(rules.audited_paths(), vec![PathBuf::from("/p/keys/*.pem")]);
+
+        rules.enable_audit(AuditLevel::All, cwd);
+        assert!(rules.is_audited(readme));
+        assert_eq!(rules.audited_paths(), vec![PathBuf::from("/p/*")]);
+    }
+
+    #[test]
+    fn test_observed_paths() {
+        let cwd = Path::new("/p");
+        let settings = make_settings(vec![]);
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+        assert!(rules.observed_paths().is_empty());
+        assert_eq!(rules.secret_scan_limit(), None);
+
+        rules.enable_secret_scan(4096, cwd);
+        assert_eq!(rules.secret_scan_limit(), Some(4096));
+        assert_eq!(rules.observed_paths(), vec![PathBuf::from("/p/*")]);
+        rules.enable_learning(cwd);
+        assert_eq!(rules.observed_paths().len(), 2);
+    }
+
+    #[test]
+    fn test_hide_rules() {
+        let cwd = Path::new("/p");
+        let mut settings = make_settings(vec!["Read(./secret.txt)", "Write(./config.json)"]);
+        settings.access_guard.hide = vec!["Read(./.env*)".to_string()];
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        assert!(rules.is_hidden(Path::new("/p/.env")));
+        assert!(rules.is_hidden(Path::new("/p/.env.local")));
+        assert!(rules.is_denied(Path::new("/p/.env"), Operation::Read));
+        assert!(!rules.is_hidden(Path::new("/p/secret.txt")));
+
+        rules.hide_read_denied();
+        assert!(rules.is_hidden(Path::new("/p/secret.txt")));
+        assert!(!rules.is_hidden(Path::new("/p/config.json")));
+    }
+
     #[test]
     fn test_executable_exclusion() {
         let cwd = Path::new("/tmp");
         let settings = Settings {
             permissions: Permissions { deny: vec![] },
+            access_guard: GuardSettings::default(),
+            source: None,
         };
         let rules = AccessRules::new(
             &settings,
             cwd,
             vec!["/bin/cat".to_string(), "./myscript.sh".to_string()],
+            FsView::default(),
         )
         .unwrap();
 
@@ -281,4 +1270,170 @@ mod tests {
         assert!(rules.is_executable_excluded(Path::new("/tmp/myscript.sh")));
         assert!(!rules.is_executable_excluded(Path::new("/bin/ls")));
     }
+
+    #[test]
+    fn test_shared_rules_update() {
+        let cwd = Path::new("/p");
+        let shared = SharedRules::new(
+            AccessRules::new(
+                &make_settings(vec!["Read(./.env)"]),
+                cwd,
+                vec![],
+                FsView::default(),
+            )
+            .unwrap(),
+        );
+        let before = shared.load();
+
+        shared.update(|rules| rules.add_rule("Write(./*.lock)", cwd, true)).unwrap();
+        let after = shared.load();
+        assert!(after.is_denied(Path::new("/p/Cargo.lock"), Operation::Create));
+        assert!(after.is_hidden(Path::new("/p/Cargo.lock")));
+        assert!(!before.is_denied(Path::new("/p/Cargo.lock"), Operation::Create));
+
+        assert_eq!(shared.update(|rules| rules.remove_rule("Read(./.env)")), 1);
+        assert!(!shared.load().is_denied(Path::new("/p/.env"), Operation::Read));
+        assert_eq!(shared.load().rules().len(), 1);
+        assert!(shared.update(|rules| rules.add_rule("Bogus(./x)", cwd, false)).is_err());
+    }
+
+    #[test]
+    fn test_rule_diff() {
+        let cwd = Path::new("/p");
+        let mut old_settings = make_settings(vec!["Read(./.env)", "Write(./a)"]);
+        old_settings.access_guard.hide = vec!["Read(./id_rsa)".to_string()];
+        let new_settings = make_settings(vec!["Read(./.env)", "Read(./id_rsa)", "Stat(./b)"]);
+        let old = AccessRules::new(&old_settings, cwd, vec![], FsView::default()).unwrap();
+        let new = AccessRules::new(&new_settings, cwd, vec![], FsView::default()).unwrap();
+
+        let (added, removed) = old.diff(&new);
+        assert_eq!(added, vec!["Read(./id_rsa)", "Stat(./b)"]);
+        assert_eq!(removed, vec!["Write(./a)", "Read(./id_rsa) [hide]"]);
+        assert_eq!(new.diff(&new), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_audit_only_rules() {
+        let cwd = Path::new("/p");
+        let mut settings = make_settings(vec!["Read(./.env)"]);
+        settings.access_guard.audit_only = vec!["Delete(./migrations/*)".to_string(), "Read(./*)".to_string()];
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+
+        let migration = Path::new("/p/migrations/001.sql");
+        let rule = rules.matching_rule(migration, Operation::Delete).unwrap();
+        assert!(rule.audit_only && !rules.enforces(rule));
+        assert!(!rules.is_denied(migration, Operation::Delete));
+        // Enforced rules take precedence over audit-only ones
+        let rule = rules.matching_rule(Path::new("/p/.env"), Operation::Read).unwrap();
+        assert_eq!(rule.entry, "Read(./.env)");
+        assert!(rules.is_denied(Path::new("/p/.env"), Operation::Read));
+        assert!(!rules.is_denied(Path::new("/p/a.txt"), Operation::Read));
+
+        rules.set_enforce(false);
+        let rule = rules.matching_rule(Path::new("/p/.env"), Operation::Read).unwrap();
+        assert!(!rules.enforces(rule));
+        assert!(!rules.is_denied(Path::new("/p/.env"), Operation::Read));
+    }
+
+    #[test]
+    fn test_redact_rules() {
+        let rules = AccessRules::new(
+            &make_settings(vec!["Redact(./.env*)", "Read(./id_rsa)"]),
+            Path::new("/p"),
+            vec![],
+            FsView::default(),
+        )
+        .unwrap();
+
+        let env = Path::new("/p/.env.local");
+        let rule = rules.redacting_rule(env, None).unwrap();
+        assert_eq!(rule.entry, "Redact(./.env*)");
+        // Reads are allowed, writes are denied
+        assert!(!rules.is_denied(env, Operation::Read));
+        assert!(rules.is_denied(env, Operation::Write));
+        assert!(rules.is_denied(env, Operation::Overwrite));
+        assert!(rules.redacting_rule(Path::new("/p/id_rsa"), None).is_none());
+    }
+
+    #[test]
+    fn test_substitute_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let decoys = dir.path().join("decoys");
+        std::fs::create_dir(&decoys).unwrap();
+        std::fs::write(decoys.join("id_rsa"), "decoy key").unwrap();
+        let mut settings = make_settings(vec![]);
+        settings.access_guard.substitute = serde_json::from_str(
+            r#"[
+                {"rule": "Read(./.env)", "with": "stub", "content": "API_KEY=dummy"},
+                {"rule": "Read(./keys/*)", "with": "decoy"},
+                {"rule": "Read(./empty)", "with": "empty"}
+            ]"#,
+        )
+        .unwrap();
+        settings.access_guard.decoy_dir = Some("./decoys".to_string());
+        let rules = AccessRules::new(&settings, dir.path(), vec![], FsView::default()).unwrap();
+
+        let content = |path: &str| {
+            let path = dir.path().join(path);
+            let rule = rules.matching_rule(&path, Operation::Read).unwrap();
+            rules.substitute_content(rule, &path).unwrap()
+        };
+        assert!(rules.is_denied(&dir.path().join(".env"), Operation::Read));
+        assert_eq!(content(".env").as_slice(), b"API_KEY=dummy");
+        assert_eq!(content("keys/id_rsa").as_slice(), b"decoy key");
+        // Decoys missing from the directory are served empty
+        assert!(content("keys/other").is_empty());
+        assert!(content("empty").is_empty());
+
+        settings.access_guard.substitute = serde_json::from_str(
+            r#"[{"rule": "Write(./.env)", "with": "empty"}]"#,
+        )
+        .unwrap();
+        let result = AccessRules::new(&settings, dir.path(), vec![], FsView::default());
+        assert!(matches!(result, Err(RuleParseError::InvalidSubstitute(_))));
+    }
+
+    #[test]
+    fn test_protect_settings() {
+        let cwd = Path::new("/p");
+        let mut settings = make_settings(vec!["Read(./.env)"]);
+        settings.source = Some(PathBuf::from("/p/.claude/settings.json"));
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+        rules.protect_settings();
+
+        let settings_file = Path::new("/p/.claude/settings.json");
+        assert!(rules.is_denied(settings_file, Operation::Overwrite));
+        assert!(rules.is_denied(settings_file, Operation::Delete));
+        assert!(rules.is_denied(settings_file, Operation::Rename));
+        assert!(!rules.is_denied(settings_file, Operation::Read));
+        assert!(rules.is_denied(Path::new("/p/.claude"), Operation::Delete));
+        assert!(rules.is_denied(Path::new("/p/.claude"), Operation::Rename));
+        assert!(!rules.is_denied(Path::new("/p/.claude/other.json"), Operation::Write));
+
+        let implicit: Vec<&str> = rules
+            .rules()
+            .iter()
+            .filter(|rule| rule.implicit)
+            .map(|rule| rule.entry.as_str())
+            .collect();
+        assert_eq!(
+            implicit,
+            vec![
+                "Write(/p/.claude/settings.json)",
+                "Delete(/p/.claude)",
+                "Rename(/p/.claude)"
+            ]
+        );
+        assert!(rules.denied_paths().contains(&PathBuf::from("/p/.claude")));
+    }
+
+    #[test]
+    fn test_protect_settings_without_rules() {
+        let mut settings = make_settings(vec![]);
+        settings.source = Some(PathBuf::from("/p/.claude/settings.json"));
+        let cwd = Path::new("/p");
+        let mut rules = AccessRules::new(&settings, cwd, vec![], FsView::default()).unwrap();
+        rules.protect_settings();
+        assert!(rules.rules().is_empty());
+    }
 }